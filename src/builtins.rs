@@ -1,23 +1,117 @@
-use crate::renderer::{Attributes, Renderer, RenderValue, RenderError, basic_html_tag};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::iter::StepBy;
+use std::ops::Range;
+use std::rc::Rc;
+
+use crate::renderer::{Attribute, Attributes, Dialect, Renderer, RenderValue, RenderError, write_html_tag, lookup_variable};
 use crate::context::{ContextValue, RenderContext};
-use crate::template::{TemplateExprNode, TemplateTag};
+use crate::template::{Template, TemplateExprNode, TemplateTag, get_children_by_tag};
+
+
+// what the builtins keep track of while the render running on this thread goes deeper. it is
+// kept out of the context so templates can neither read nor overwrite it
+#[derive(Clone, Default)]
+struct Scope {
+    includes: Vec<String>,
+    components: HashMap<String, Rc<TemplateTag>>,
+    children: Option<Rc<RenderValue>>,
+    switch: Option<Rc<ContextValue>>,
+    // the components of each template imported during the render, shared by every scope of it
+    imports: Rc<RefCell<HashMap<String, Components>>>,
+}
+
+type Components = Rc<[Rc<TemplateTag>]>;
+
+thread_local! {
+    static SCOPE: RefCell<Scope> = RefCell::new(Scope::default());
+}
+
+fn current_scope<T>(read: impl FnOnce(&Scope) -> T) -> T {
+    SCOPE.with(|scope| read(&scope.borrow()))
+}
+
+// changes the scope until the returned guard is dropped
+fn enter_scope(change: impl FnOnce(&mut Scope)) -> ScopeGuard {
+    SCOPE.with(|scope| {
+        let mut scope = scope.borrow_mut();
+        let previous = scope.clone();
+        change(&mut scope);
+        ScopeGuard(previous)
+    })
+}
+
+// a render of its own, which imports templates afresh
+pub(crate) fn render_scope() -> ScopeGuard {
+    enter_scope(|scope| scope.imports = Rc::default())
+}
+
+pub(crate) struct ScopeGuard(Scope);
+
+impl Drop for ScopeGuard {
+    fn drop(&mut self) {
+        let previous = std::mem::take(&mut self.0);
+        SCOPE.with(|scope| *scope.borrow_mut() = previous);
+    }
+}
+
 
+pub(crate) fn write_html(mut attrs: Attributes, expr: &[TemplateExprNode], renderer: &Renderer, context: &RenderContext, out: &mut dyn fmt::Write) -> Result<(), RenderError> {
+    if renderer.dialect() == Dialect::Xhtml && !attrs.has("xmlns") {
+        attrs.push(Attribute("xmlns".into(), Some("http://www.w3.org/1999/xhtml".into())));
+    }
+    out.write_str(renderer.dialect().prolog())?;
+    write_html_tag("html", &attrs, expr, renderer, context, out)
+}
 
 pub(crate) fn do_html(attrs: Attributes, expr: &[TemplateExprNode], renderer: &Renderer, context: &RenderContext) -> Result<RenderValue, RenderError> {
-    let mut v: Vec<RenderValue> = vec!["<!doctype html5>".into()];
-    v.push(basic_html_tag("html".into(), &attrs, &expr, renderer, context)?.into());
-    Ok(v.into())
+    let mut out = String::new();
+    write_html(attrs, expr, renderer, context, &mut out)?;
+    Ok(RenderValue::Safe(out))
+}
+
+pub(crate) fn write_document(_: Attributes, expr: &[TemplateExprNode], renderer: &Renderer, context: &RenderContext, out: &mut dyn fmt::Write) -> Result<(), RenderError> {
+    out.write_str(renderer.dialect().prolog())?;
+    renderer.emit_multiple(expr, context, out)
+}
+
+pub(crate) fn do_document(attrs: Attributes, expr: &[TemplateExprNode], renderer: &Renderer, context: &RenderContext) -> Result<RenderValue, RenderError> {
+    let mut out = String::new();
+    write_document(attrs, expr, renderer, context, &mut out)?;
+    Ok(RenderValue::Safe(out))
 }
 
 pub(crate) fn do_is_set(_: Attributes, expr: &[TemplateExprNode], _render: &Renderer, context: &RenderContext) -> Result<RenderValue, RenderError> {
-    match expr.get(0) {
-        Some(TemplateExprNode::Identifier(ident)) => {
-            Ok(match context.get(ident) {
+    match expr.first() {
+        Some(TemplateExprNode::Identifier(ident, _)) => {
+            Ok(match lookup_variable(ident, context) {
                 Some(_) => RenderValue::Boolean(true),
                 None => RenderValue::Boolean(false)
             })
         },
-        _ => Err(RenderError::IsSet("expected identifier".into(), expr.to_vec()))
+        _ => Err(RenderError::IsSet("expected identifier".into()))
+    }
+}
+
+pub(crate) fn do_is_null(_: Attributes, expr: &[TemplateExprNode], renderer: &Renderer, context: &RenderContext) -> Result<RenderValue, RenderError> {
+    match expr {
+        [value] => Ok(matches!(renderer.evaluate(value, context)?, RenderValue::Null | RenderValue::Empty).into()),
+        _ => Err(RenderError::IsNull("expected a single argument".into())),
+    }
+}
+
+pub(crate) fn do_default(_: Attributes, expr: &[TemplateExprNode], renderer: &Renderer, context: &RenderContext) -> Result<RenderValue, RenderError> {
+    let value = expr.first()
+        .ok_or_else(|| RenderError::Default("value not found".into()))?;
+    let fallback = expr.get(1..)
+        .unwrap_or_default();
+
+    match value {
+        TemplateExprNode::Identifier(ident, _) if ident.starts_with('$') && matches!(lookup_variable(ident, context), None | Some(ContextValue::Null)) => {
+            renderer.evaluate_multiple(fallback, context)
+        },
+        _ => renderer.evaluate(value, context),
     }
 }
 
@@ -25,244 +119,708 @@ pub(crate) fn do_cmp_op<F>(_: Attributes, expr: &[TemplateExprNode], renderer: &
 where
     F: FnOnce(ContextValue, ContextValue) -> bool,
 {
-    let exp1 = expr.get(0)
-        .and_then(|e| {
-            match e {
-                TemplateExprNode::Identifier(ident) => Some(context.get(ident).cloned().unwrap_or(ContextValue::String(ident.clone()))),
-                TemplateExprNode::Integer(i) => Some(ContextValue::Integer(*i)),
-                TemplateExprNode::Tag(_tag) => Some(renderer.evaluate(e, context).unwrap().into()),
-            }
-        })
-        .ok_or_else(|| RenderError::Cmp("missing expr 1".into(), expr.to_vec()))?;
-    let exp2 = expr.get(1)
-        .and_then(|e| {
-            match e {
-                TemplateExprNode::Identifier(ident) => Some(context.get(ident).cloned().unwrap_or(ContextValue::String(ident.clone()))),
-                TemplateExprNode::Integer(i) => Some(ContextValue::Integer(*i)),
-                TemplateExprNode::Tag(_tag) => Some(renderer.evaluate(e, context).ok()?.into()),
-            }
-        })
-        .ok_or_else(|| RenderError::Cmp("missing expr 2".into(), expr.to_vec()))?;
+    let exp1: ContextValue = renderer.evaluate(expr.first()
+                                               .ok_or_else(|| RenderError::Cmp("missing expr 1".into()))?,
+                                               context)?
+        .into();
+    let exp2: ContextValue = renderer.evaluate(expr.get(1)
+                                               .ok_or_else(|| RenderError::Cmp("missing expr 2".into()))?,
+                                               context)?
+        .into();
 
     Ok(op(exp1, exp2).into())
 }
 
 
-pub(crate) fn do_math_op<F>(_: Attributes, expr: &[TemplateExprNode], renderer: &Renderer, context: &RenderContext, op: F) -> Result<RenderValue, RenderError>
+fn math_operand(expr: &[TemplateExprNode], index: usize, renderer: &Renderer, context: &RenderContext) -> Result<RenderValue, RenderError> {
+    let value = renderer.evaluate(expr.get(index)
+                                  .ok_or_else(|| RenderError::Math(format!("missing expr {}", index + 1)))?,
+                                  context)?;
+    match value.as_float() {
+        Some(_) => Ok(value),
+        None => Err(RenderError::Math(format!("`{}` is not a number", value.into_text()))),
+    }
+}
+
+// integers stay integers, anything involving a float is done in floats. `int_op` returns
+// `None` when the result does not fit in an i64 or the right side is a zero divisor
+pub(crate) fn do_math_op<I, F>(_: Attributes, expr: &[TemplateExprNode], renderer: &Renderer, context: &RenderContext, int_op: I, float_op: F) -> Result<RenderValue, RenderError>
 where
-    F: FnOnce(i64, i64) -> i64
+    I: FnOnce(i64, i64) -> Option<i64>,
+    F: FnOnce(f64, f64) -> f64,
 {
-    let exp1 = expr.get(0)
-        .and_then(|e| renderer.evaluate(e, context).ok())
-        .and_then(|rv| rv.as_int())
-        .ok_or_else(|| RenderError::Math("missing expr 1".into(), expr.to_vec()))?;
+    let exp1 = math_operand(expr, 0, renderer, context)?;
+    let exp2 = math_operand(expr, 1, renderer, context)?;
+
+    Ok(match (exp1.as_int(), exp2.as_int()) {
+        (Some(a), Some(b)) => int_op(a, b)
+            .ok_or(if b == 0 { RenderError::DivisionByZero } else { RenderError::Overflow })?
+            .into(),
+        _ => float_op(exp1.as_float().unwrap_or_default(), exp2.as_float().unwrap_or_default()).into(),
+    })
+}
 
-    let exp2 = expr.get(1)
-        .and_then(|e| renderer.evaluate(e, context).ok())
-        .and_then(|rv| rv.as_int())
-        .ok_or_else(|| RenderError::Math("missing expr 2".into(), expr.to_vec()))?;
+const MAX_DECIMALS: usize = 100;
+
+pub(crate) fn do_format_number(_: Attributes, expr: &[TemplateExprNode], renderer: &Renderer, context: &RenderContext) -> Result<RenderValue, RenderError> {
+    let value = renderer.evaluate(expr.first()
+                                  .ok_or_else(|| RenderError::FormatNumber("number not found".into()))?,
+                                  context)?;
+    let number = value.as_float()
+        .ok_or_else(|| RenderError::FormatNumber(format!("`{}` is not a number", value.clone().into_text())))?;
+
+    let decimals = match expr.get(1) {
+        Some(e) => renderer.evaluate(e, context)?
+            .as_int()
+            .and_then(|d| usize::try_from(d).ok())
+            .filter(|d| *d <= MAX_DECIMALS)
+            .ok_or_else(|| RenderError::FormatNumber(format!("decimal places must be an integer from 0 to {}", MAX_DECIMALS)))?,
+        None => 0,
+    };
 
-    Ok(op(exp1, exp2).into())
+    Ok(RenderValue::String(format!("{:.*}", decimals, number)))
 }
 
 
+// the index of the branch of an `if` to take, if any
+pub(crate) fn if_branch(expr: &[TemplateExprNode], renderer: &Renderer, context: &RenderContext) -> Result<Option<usize>, RenderError> {
+    let conditional = expr.first()
+        .ok_or_else(|| RenderError::If("condition not found".into()))?;
+
+    if renderer.evaluate(conditional, context)?.is_truthy() {
+        expr.get(1)
+            .ok_or_else(|| RenderError::If("code block not found".into()))?;
+        Ok(Some(1))
+    }
+    else {
+        Ok(Some(2).filter(|i| *i < expr.len()))
+    }
+}
+
 pub(crate) fn do_if(_: Attributes, expr: &[TemplateExprNode], renderer: &Renderer, context: &RenderContext) -> Result<RenderValue, RenderError> {
-    let conditional = expr.get(0)
-        .ok_or_else(|| RenderError::If("condition not found".into(), expr.to_vec()))?;
+    match if_branch(expr, renderer, context)? {
+        Some(branch) => renderer.evaluate(&expr[branch], context),
+        None => Ok(RenderValue::Empty),
+    }
+}
+
+pub(crate) fn write_if(_: Attributes, expr: &[TemplateExprNode], renderer: &Renderer, context: &RenderContext, out: &mut dyn fmt::Write) -> Result<(), RenderError> {
+    match if_branch(expr, renderer, context)? {
+        Some(branch) => renderer.emit(&expr[branch], context, out),
+        None => Ok(()),
+    }
+}
 
-    let result = renderer.evaluate(conditional, context)?;
+// whether the body of a `when` or `unless`, everything after the condition, is used
+pub(crate) fn when_matches(expr: &[TemplateExprNode], renderer: &Renderer, context: &RenderContext, expected: bool) -> Result<bool, RenderError> {
+    let conditional = expr.first()
+        .ok_or_else(|| RenderError::When("condition not found".into()))?;
 
-    let is_true = match result {
-        RenderValue::Boolean(b) => b,
-        RenderValue::String(s) if s != "" => true,
-        RenderValue::Integer(i) if i != 0 => true,
-        _ => false
-    };
+    Ok(renderer.evaluate(conditional, context)?.is_truthy() == expected)
+}
+
+pub(crate) fn do_when(_: Attributes, expr: &[TemplateExprNode], renderer: &Renderer, context: &RenderContext, expected: bool) -> Result<RenderValue, RenderError> {
+    if when_matches(expr, renderer, context, expected)? {
+        renderer.evaluate_multiple(&expr[1..], context)
+    }
+    else {
+        Ok(RenderValue::Empty)
+    }
+}
+
+pub(crate) fn write_when(_: Attributes, expr: &[TemplateExprNode], renderer: &Renderer, context: &RenderContext, out: &mut dyn fmt::Write, expected: bool) -> Result<(), RenderError> {
+    if when_matches(expr, renderer, context, expected)? {
+        renderer.emit_multiple(&expr[1..], context, out)?;
+    }
+    Ok(())
+}
 
-    Ok(
-        if is_true {
-            renderer.evaluate(expr
-                              .get(1)
-                              .ok_or_else(|| RenderError::If("code block not found".into(), expr.to_vec()))?,
-                              context)?
+// what a `cond` clause evaluates to when it matches
+pub(crate) fn cond_clause_body(clause: &TemplateExprNode) -> &[TemplateExprNode] {
+    match clause {
+        TemplateExprNode::Tag(clause) if clause.tag.is_empty() => clause.children.get(1..).unwrap_or_default(),
+        TemplateExprNode::Tag(clause) => &clause.children,
+        _ => &[],
+    }
+}
+
+// the index of the first `cond` clause that matches
+pub(crate) fn cond_clause(expr: &[TemplateExprNode], renderer: &Renderer, context: &RenderContext) -> Result<Option<usize>, RenderError> {
+    for (i, clause) in expr.iter().enumerate() {
+        let matches = match clause {
+            TemplateExprNode::Tag(clause) if clause.tag == "else" => true,
+            // `((eq $a b) body...)`
+            TemplateExprNode::Tag(clause) if clause.tag.is_empty() => {
+                let conditional = clause.children.first()
+                    .ok_or_else(|| RenderError::Cond("clause is missing a condition".into()))?;
+                renderer.evaluate(conditional, context)?.is_truthy()
+            },
+            // `($a body...)`
+            TemplateExprNode::Tag(clause) => {
+                let conditional = TemplateExprNode::Identifier(clause.tag.clone(), clause.span.clone());
+                renderer.evaluate(&conditional, context)?.is_truthy()
+            },
+            _ => return Err(RenderError::Cond("expected a clause of the form (condition body...)".into()))
+        };
+
+        if matches {
+            return Ok(Some(i))
         }
-        else {
-            match expr.get(2) {
-                Some(e) => renderer.evaluate(e, context)?,
-                None => RenderValue::Empty,
-            }
-        })
+    }
+    Ok(None)
 }
 
-pub(crate) fn do_case(_: Attributes, expr: &[TemplateExprNode], renderer: &Renderer, context: &RenderContext) -> Result<RenderValue, RenderError> {
-    let condition = expr.get(0)
-        .ok_or_else(|| RenderError::Case("variant not found".into(), expr.to_vec()))?;
+pub(crate) fn do_cond(_: Attributes, expr: &[TemplateExprNode], renderer: &Renderer, context: &RenderContext) -> Result<RenderValue, RenderError> {
+    match cond_clause(expr, renderer, context)? {
+        Some(clause) => renderer.evaluate_multiple(cond_clause_body(&expr[clause]), context),
+        None => Ok(RenderValue::Empty),
+    }
+}
+
+pub(crate) fn write_cond(_: Attributes, expr: &[TemplateExprNode], renderer: &Renderer, context: &RenderContext, out: &mut dyn fmt::Write) -> Result<(), RenderError> {
+    match cond_clause(expr, renderer, context)? {
+        Some(clause) => renderer.emit_multiple(cond_clause_body(&expr[clause]), context, out),
+        None => Ok(()),
+    }
+}
+
+pub(crate) fn do_and(_: Attributes, expr: &[TemplateExprNode], renderer: &Renderer, context: &RenderContext) -> Result<RenderValue, RenderError> {
+    for e in expr {
+        if !renderer.evaluate(e, context)?.is_truthy() {
+            return Ok(RenderValue::Boolean(false))
+        }
+    }
+    Ok(RenderValue::Boolean(true))
+}
+
+pub(crate) fn do_or(_: Attributes, expr: &[TemplateExprNode], renderer: &Renderer, context: &RenderContext) -> Result<RenderValue, RenderError> {
+    for e in expr {
+        if renderer.evaluate(e, context)?.is_truthy() {
+            return Ok(RenderValue::Boolean(true))
+        }
+    }
+    Ok(RenderValue::Boolean(false))
+}
+
+pub(crate) fn do_not(_: Attributes, expr: &[TemplateExprNode], renderer: &Renderer, context: &RenderContext) -> Result<RenderValue, RenderError> {
+    match expr {
+        [e] => Ok(RenderValue::Boolean(!renderer.evaluate(e, context)?.is_truthy())),
+        _ => Err(RenderError::Not("expected exactly one argument".into()))
+    }
+}
 
-    let switch_value = context.get("__switch")
-        .ok_or_else(|| RenderError::Case("builtin switch variable not found?".into(), expr.to_vec()))?;
+fn case_body<'a>(expr: &'a [TemplateExprNode], renderer: &Renderer, context: &RenderContext) -> Result<Option<&'a [TemplateExprNode]>, RenderError> {
+    let condition = expr.first()
+        .ok_or_else(|| RenderError::Case("variant not found".into()))?;
+
+    let switch_value = current_scope(|scope| scope.switch.clone())
+        .ok_or_else(|| RenderError::Case("case is not inside a switch".into()))?;
 
     let body = expr.get(1..)
         .unwrap_or_default();
 
-    match (condition, switch_value) {
-        (TemplateExprNode::Identifier(condition_str), ContextValue::String(switch_str)) if condition_str == switch_str => {
-            renderer.evaluate_multiple(body, context)
-        },
-        _ => return Ok(RenderValue::Empty)
+    if ContextValue::from(renderer.evaluate(condition, context)?) == *switch_value {
+        Ok(Some(body))
+    }
+    else {
+        Ok(None)
     }
 }
 
-pub(crate) fn do_switch(_: Attributes, expr: &[TemplateExprNode], renderer: &Renderer, context: &RenderContext) -> Result<RenderValue, RenderError> {
-    let variable = renderer.evaluate(expr.get(0)
-                                     .ok_or_else(|| RenderError::Switch("variable not found".into(), expr.to_vec()))?,
+pub(crate) fn do_case(_: Attributes, expr: &[TemplateExprNode], renderer: &Renderer, context: &RenderContext) -> Result<RenderValue, RenderError> {
+    match case_body(expr, renderer, context)? {
+        Some(body) => renderer.evaluate_multiple(body, context),
+        None => Ok(RenderValue::Empty),
+    }
+}
+
+pub(crate) fn write_case(_: Attributes, expr: &[TemplateExprNode], renderer: &Renderer, context: &RenderContext, out: &mut dyn fmt::Write) -> Result<(), RenderError> {
+    match case_body(expr, renderer, context)? {
+        Some(body) => renderer.emit_multiple(body, context, out),
+        None => Ok(()),
+    }
+}
+
+// the value the cases of a `switch` are compared to, until the returned guard is dropped
+fn switch_scope(expr: &[TemplateExprNode], renderer: &Renderer, context: &RenderContext) -> Result<ScopeGuard, RenderError> {
+    let variable = renderer.evaluate(expr.first()
+                                     .ok_or_else(|| RenderError::Switch("variable not found".into()))?,
                                      context)?;
-    let cases = expr.get(1..);
-    let mut context = context.clone();
-    context.insert("__switch", &variable);
-    Ok(cases.iter()
-        .map(|case| {
-            renderer.evaluate_multiple(*case, &context)
-        })
-        .collect::<Result<Vec<_>, _>>()?
-        .into())
-}
-
-
-fn parse_range(tag: &TemplateTag, renderer: &Renderer, context: &RenderContext) -> Option<ContextValue> {
-    let min = tag.children.get(0)
-        .and_then(|e| renderer.evaluate(e, context).ok())
-        .and_then(|e| e.as_int())?;
-    let max = tag.children.get(1)
-        .and_then(|e| renderer.evaluate(e, context).ok())
-        .and_then(|e| e.as_int())?;
-    let step = tag.children.get(2)
-        .and_then(|e| renderer.evaluate(e, context).ok())
-        .and_then(|e| e.as_int())
-        .unwrap_or(1) as usize;
-
-    let range = (min..max)
-        .step_by(step)
-        .map(Into::into)
-        .collect();
+    let variable = Rc::new(ContextValue::from(variable));
+    Ok(enter_scope(|scope| scope.switch = Some(variable)))
+}
 
-    Some(ContextValue::Vec(range))
+pub(crate) fn do_switch(_: Attributes, expr: &[TemplateExprNode], renderer: &Renderer, context: &RenderContext) -> Result<RenderValue, RenderError> {
+    let _switch = switch_scope(expr, renderer, context)?;
+    renderer.evaluate_multiple(&expr[1..], context)
+}
+
+pub(crate) fn write_switch(_: Attributes, expr: &[TemplateExprNode], renderer: &Renderer, context: &RenderContext, out: &mut dyn fmt::Write) -> Result<(), RenderError> {
+    let _switch = switch_scope(expr, renderer, context)?;
+    renderer.emit_multiple(&expr[1..], context, out)
+}
+
+
+// the numbers a `range` goes through, which are made one at a time as the `for` needs them
+fn parse_range(tag: &TemplateTag, renderer: &Renderer, context: &RenderContext) -> Result<StepBy<Range<i64>>, RenderError> {
+    let bound = |index: usize| -> Result<i64, RenderError> {
+        let value = renderer.evaluate(tag.children.get(index)
+                                      .ok_or_else(|| RenderError::For("invalid range".into()))?,
+                                      context)?;
+        value.as_int()
+            .ok_or_else(|| RenderError::For(format!("range bound `{}` is not an integer", value.into_text())))
+    };
+    let min = bound(0)?;
+    let max = bound(1)?;
+    let step = match tag.children.get(2) {
+        Some(_) => bound(2)?,
+        None => 1,
+    };
+    let step = usize::try_from(step)
+        .ok()
+        .filter(|step| *step > 0)
+        .ok_or(RenderError::RangeStep(step))?;
+    let iterations = if max > min { (max.abs_diff(min) - 1) / step as u64 + 1 } else { 0 };
+    renderer.check_iterations(usize::try_from(iterations).unwrap_or(usize::MAX))?;
+
+    Ok((min..max).step_by(step))
 }
 
-pub(crate) fn do_for(attrs: Attributes, expr: &[TemplateExprNode], renderer: &Renderer, context: &RenderContext) -> Result<RenderValue, RenderError> {
-    let in_position = expr.iter()
+fn in_position(expr: &[TemplateExprNode]) -> Option<usize> {
+    expr.iter()
         .position(|b| {
-            match b {
-                TemplateExprNode::Identifier(ident) if ident == "in" => true,
-                _ => false,
-            }
-        });
-
-    if let Some(in_position) = in_position {
-        let iterable = expr.get(in_position+1)
-            .map(|e| {
-                match e {
-                    TemplateExprNode::Identifier(ident) => {
-                        crate::renderer::expand_variable(ident, renderer, context)
-                            .map(|k| k.into())
-                    },
-                    TemplateExprNode::Tag(tag) if tag.tag == "range" => {
-                        parse_range(tag, renderer, context)
-                            .ok_or_else(|| RenderError::For("invalid range".into(), attrs.clone(), expr.to_vec()))
-                    },
-                    _ => Err(RenderError::For("iteration variable is not a valid type".into(), attrs.clone(), expr.to_vec()))
-                }
-            })
-            .ok_or_else(|| RenderError::For("no iteration variable specified".into(), attrs.clone(), expr.to_vec()))??;
-        let body = expr.get(in_position+2..)
-            .unwrap_or_default();
-
-        match iterable {
-            ContextValue::Vec(v) => {
-                enum IterType {
-                    Normal(String),
-                    Enum(String, String),
-                }
+            matches!(b, TemplateExprNode::Identifier(ident, _) if ident == "in")
+        })
+}
 
-                let val = expr.get(in_position-1)
-                    .and_then(|e| {
-                        match e {
-                            TemplateExprNode::Identifier(_) => renderer.evaluate(e, context).map(|e| IterType::Normal(e.finalize())).ok(),
-                            TemplateExprNode::Tag(tag) if tag.tag == "enumerate" => {
-                                let index = tag.children.get(0)
-                                    .and_then(TemplateExprNode::as_identifier)?;
-                                let iter = tag.children.get(1)
-                                    .and_then(TemplateExprNode::as_identifier)?;
-                                Some(IterType::Enum(iter.clone(), index.clone()))
-                            },
-                            _ => None
-                        }
-                    })
-                    .ok_or_else(|| RenderError::For("missing variable to iterate over".into(), attrs, expr.to_vec()))?;
-
-                let mut second_context = context.clone();
-                Ok(v.iter()
-                   .enumerate()
-                   .map(|(i, value)| {
-                       match &val {
-                           IterType::Normal(val) => {
-                               second_context.insert(val.clone(), value.clone());
-                           }
-                           IterType::Enum(iter, index) => {
-                               second_context.insert(iter.clone(), value.clone());
-                               second_context.insert(index.clone(), i);
-                           }
-                       }
-                       renderer.evaluate_multiple(body, &second_context)
-                   })
-                   .collect::<Result<Vec<_>, RenderError>>()?
-                   .into())
-            },
-            ContextValue::Object(o) => {
-                let key_var = expr.get(in_position-2)
-                    .and_then(|a| renderer.evaluate(a, context).ok())
-                    .map(|e| e.finalize())
-                    .ok_or_else(|| RenderError::For("missing key variable to iterate over".into(), attrs.clone(), expr.to_vec()))?;
-                let value_var = expr.get(in_position-1)
-                    .and_then(|a| renderer.evaluate(a, context).ok())
-                    .map(|e| e.finalize())
-                    .ok_or_else(|| RenderError::For("missing value variable to iterate over".into(), attrs.clone(), expr.to_vec()))?;
-                let mut second_context = context.clone();
-                Ok(o.0.iter()
-                   .map(|(key, value)| {
-                       second_context.insert(key_var.clone(), ContextValue::String(key.clone()));
-                       second_context.insert(value_var.clone(), value.clone());
-                       renderer.evaluate_multiple(body, &second_context)
-                   })
-                   .collect::<Result<Vec<_>, RenderError>>()?
-                   .into())
-            },
-            _ => Err(RenderError::For("element is not iterable".into(), attrs, expr.to_vec()))
-        }
+// what a `for` evaluates on every iteration
+pub(crate) fn for_body(expr: &[TemplateExprNode]) -> &[TemplateExprNode] {
+    in_position(expr)
+        .and_then(|in_position| expr.get(in_position+2..))
+        .unwrap_or_default()
+}
+
+// calls `each` with the context of every iteration of a `for` in turn
+pub(crate) fn for_each<F>(expr: &[TemplateExprNode], renderer: &Renderer, context: &RenderContext, each: F) -> Result<(), RenderError>
+where
+    F: FnMut(&RenderContext) -> Result<(), RenderError>,
+{
+    let in_position = in_position(expr)
+        .ok_or_else(|| RenderError::For("invalid syntax".into()))?;
+
+    let iterable: ContextValue = match expr.get(in_position+1) {
+        Some(TemplateExprNode::Identifier(ident, _)) => {
+            crate::renderer::expand_variable(ident, renderer, context)?.into()
+        },
+        Some(TemplateExprNode::Tag(tag)) if tag.tag == "range" => {
+            let range = parse_range(tag, renderer, context)?;
+            return for_each_item(expr, in_position, range.map(ContextValue::from), renderer, context, each)
+        },
+        Some(_) => return Err(RenderError::For("iteration variable is not a valid type".into())),
+        None => return Err(RenderError::For("no iteration variable specified".into())),
+    };
+
+    match iterable {
+        ContextValue::Vec(v) => {
+            renderer.check_iterations(v.len())?;
+            for_each_item(expr, in_position, v.into_iter(), renderer, context, each)
+        },
+        ContextValue::Object(o) => {
+            renderer.check_iterations(o.0.len())?;
+            let key_var = in_position.checked_sub(2)
+                .and_then(|i| expr.get(i))
+                .ok_or_else(|| RenderError::For("missing key variable to iterate over".into()))
+                .and_then(|a| renderer.evaluate(a, context))?
+                .into_text();
+            let value_var = in_position.checked_sub(1)
+                .and_then(|i| expr.get(i))
+                .ok_or_else(|| RenderError::For("missing value variable to iterate over".into()))
+                .and_then(|a| renderer.evaluate(a, context))?
+                .into_text();
+            each_iteration(o.0.into_iter().map(|(key, value)| [(key_var.clone(), ContextValue::String(key)), (value_var.clone(), value)]),
+                           renderer, context, each)
+        },
+        _ => Err(RenderError::For("element is not iterable".into()))
     }
-    else {
-        Err(RenderError::For("invalid syntax".into(), attrs, expr.to_vec()))
+}
+
+// iterates `items` with the variable, or `(enumerate index item)`, in front of the `in`
+fn for_each_item<I, F>(expr: &[TemplateExprNode], in_position: usize, items: I, renderer: &Renderer, context: &RenderContext, each: F) -> Result<(), RenderError>
+where
+    I: Iterator<Item = ContextValue>,
+    F: FnMut(&RenderContext) -> Result<(), RenderError>,
+{
+    match in_position.checked_sub(1).and_then(|i| expr.get(i)) {
+        Some(e @ TemplateExprNode::Identifier(..)) => {
+            let var = renderer.evaluate(e, context)?.into_text();
+            each_iteration(items.map(|value| [(var.clone(), value)]), renderer, context, each)
+        },
+        Some(TemplateExprNode::Tag(tag)) if tag.tag == "enumerate" => {
+            let (index, var) = tag.children.first()
+                .and_then(TemplateExprNode::as_identifier)
+                .zip(tag.children.get(1).and_then(TemplateExprNode::as_identifier))
+                .ok_or_else(|| RenderError::For("missing variable to iterate over".into()))?;
+            each_iteration(items.enumerate().map(|(i, value)| [(var.clone(), value), (index.clone(), ContextValue::from(i))]),
+                           renderer, context, each)
+        },
+        _ => Err(RenderError::For("missing variable to iterate over".into())),
     }
 }
 
+// sets the variables of each iteration and calls `each`, every iteration is a step of its own
+// so that a loop with nothing in it still spends fuel
+fn each_iteration<I, V, F>(iterations: I, renderer: &Renderer, context: &RenderContext, mut each: F) -> Result<(), RenderError>
+where
+    I: Iterator<Item = V>,
+    V: IntoIterator<Item = (String, ContextValue)>,
+    F: FnMut(&RenderContext) -> Result<(), RenderError>,
+{
+    let mut iteration_context = context.clone();
+    for variables in iterations {
+        let _step = renderer.step()?;
+        for (name, value) in variables {
+            iteration_context.insert(name, value);
+        }
+        each(&iteration_context)?;
+    }
+    Ok(())
+}
+
+pub(crate) fn do_for(_: Attributes, expr: &[TemplateExprNode], renderer: &Renderer, context: &RenderContext) -> Result<RenderValue, RenderError> {
+    let mut values = Vec::new();
+    for_each(expr, renderer, context, |context| {
+        values.push(renderer.evaluate_multiple(for_body(expr), context)?);
+        Ok(())
+    })?;
+    Ok(values.into())
+}
+
+pub(crate) fn write_for(_: Attributes, expr: &[TemplateExprNode], renderer: &Renderer, context: &RenderContext, out: &mut dyn fmt::Write) -> Result<(), RenderError> {
+    for_each(expr, renderer, context, |context| renderer.emit_multiple(for_body(expr), context, out))
+}
+
 pub(crate) fn do_get(_: Attributes, expr: &[TemplateExprNode], renderer: &Renderer, context: &RenderContext) -> Result<RenderValue, RenderError> {
-    let indexable = expr.get(0)
-        .and_then(|e| renderer.evaluate(e, context).ok())
-        .unwrap();
+    let indexable = renderer.evaluate(expr.first()
+                                      .ok_or_else(|| RenderError::Get("nothing to index".into()))?,
+                                      context)?;
 
-    let index = expr.get(1)
-        .and_then(|e| renderer.evaluate(e, context).ok())
-        .unwrap();
+    let index = renderer.evaluate(expr.get(1)
+                                  .ok_or_else(|| RenderError::Get("index not found".into()))?,
+                                  context)?;
 
     match (indexable, index){
         (RenderValue::Vec(v), RenderValue::Integer(i)) => {
-            Ok(v.get(i as usize)
-                .ok_or_else(|| RenderError::Get("array out of bounds".into(), expr.to_vec()))?
+            Ok(usize::try_from(i)
+                .ok()
+                .and_then(|i| v.get(i))
+                .ok_or_else(|| RenderError::Get("array out of bounds".into()))?
                 .clone())
 
         },
         (RenderValue::Object(o), RenderValue::String(s)) => {
-            Ok(o.get(&s).ok_or_else(|| RenderError::Get("array out of bounds".into(), expr.to_vec()))?.clone())
+            Ok(o.get(&s).ok_or_else(|| RenderError::Get(format!("no key `{}` in object", s)))?.clone())
+        },
+        _ => Err(RenderError::Get("invalid index/indexable".into()))
+    }
+}
+
+pub(crate) fn do_raw(_: Attributes, expr: &[TemplateExprNode], renderer: &Renderer, context: &RenderContext) -> Result<RenderValue, RenderError> {
+    Ok(RenderValue::Safe(renderer.evaluate_multiple(expr, context)?.into_text()))
+}
+
+
+fn block_name(tag: &TemplateTag) -> Option<&String> {
+    tag.children.first()
+        .and_then(TemplateExprNode::as_identifier)
+}
+
+// replaces `(super)` in an overriding block's body with the body of the block it overrides
+fn substitute_super(expr: &[TemplateExprNode], parent_body: &[TemplateExprNode]) -> Vec<TemplateExprNode> {
+    expr.iter()
+        .flat_map(|e| {
+            match e {
+                TemplateExprNode::Tag(tag) if tag.tag == "super" => parent_body.to_vec(),
+                TemplateExprNode::Tag(tag) if tag.tag == "block" => vec![e.clone()],
+                TemplateExprNode::Tag(tag) => {
+                    vec![TemplateExprNode::Tag(TemplateTag {
+                        tag: tag.tag.clone(),
+                        attrs: tag.attrs.clone(),
+                        children: substitute_super(&tag.children, parent_body),
+                        span: tag.span.clone(),
+                    })]
+                },
+                _ => vec![e.clone()],
+            }
+        })
+        .collect()
+}
+
+// replaces every block in a parent template with the child's block of the same name
+fn merge_blocks(expr: &TemplateExprNode, overrides: &[TemplateTag]) -> TemplateExprNode {
+    match expr {
+        TemplateExprNode::Tag(tag) => {
+            let children = tag.children.iter()
+                .map(|e| merge_blocks(e, overrides))
+                .collect::<Vec<_>>();
+            let children = match (tag.tag.as_str(), block_name(tag)) {
+                ("block", Some(name)) => {
+                    match overrides.iter().find(|o| block_name(o) == Some(name)) {
+                        Some(over) => {
+                            std::iter::once(children[0].clone())
+                                .chain(substitute_super(&over.children[1..], &children[1..]))
+                                .collect()
+                        },
+                        None => children,
+                    }
+                },
+                _ => children,
+            };
+            TemplateExprNode::Tag(TemplateTag {
+                tag: tag.tag.clone(),
+                attrs: tag.attrs.clone(),
+                children,
+                span: tag.span.clone(),
+            })
+        },
+        _ => expr.clone(),
+    }
+}
+
+// the template at the top of an `extends` chain with every block replaced
+fn extended_template(expr: &[TemplateExprNode], renderer: &Renderer, context: &RenderContext) -> Result<TemplateExprNode, RenderError> {
+    let mut name = renderer.evaluate(expr.first()
+                                     .ok_or_else(|| RenderError::Extends("template name not found".into()))?,
+                                     context)?
+        .into_text();
+    let mut overrides = get_children_by_tag(expr.get(1..).unwrap_or_default(), "block");
+    let mut seen = Vec::new();
+
+    loop {
+        if seen.contains(&name) {
+            return Err(RenderError::Extends(format!("template `{}` extends itself", name)))
+        }
+        let parent = renderer.load_template(&name)?;
+        let merged = merge_blocks(&parent.expr, &overrides);
+        seen.push(name);
+
+        match merged {
+            TemplateExprNode::Tag(tag) if tag.tag == "extends" => {
+                name = renderer.evaluate(tag.children.first()
+                                         .ok_or_else(|| RenderError::Extends("template name not found".into()))?,
+                                         context)?
+                    .into_text();
+                // blocks the intermediate template doesn't mention still override the ones further up
+                let mut merged_overrides = get_children_by_tag(tag.children.get(1..).unwrap_or_default(), "block");
+                for over in overrides {
+                    if !merged_overrides.iter().any(|o| block_name(o) == block_name(&over)) {
+                        merged_overrides.push(over);
+                    }
+                }
+                overrides = merged_overrides;
+            },
+            _ => return Ok(merged),
+        }
+    }
+}
+
+pub(crate) fn do_extends(_: Attributes, expr: &[TemplateExprNode], renderer: &Renderer, context: &RenderContext) -> Result<RenderValue, RenderError> {
+    renderer.evaluate(&extended_template(expr, renderer, context)?, context)
+}
+
+pub(crate) fn write_extends(_: Attributes, expr: &[TemplateExprNode], renderer: &Renderer, context: &RenderContext, out: &mut dyn fmt::Write) -> Result<(), RenderError> {
+    renderer.emit(&extended_template(expr, renderer, context)?, context, out)
+}
+
+pub(crate) fn do_block(_: Attributes, expr: &[TemplateExprNode], renderer: &Renderer, context: &RenderContext) -> Result<RenderValue, RenderError> {
+    renderer.evaluate_multiple(expr.get(1..).unwrap_or_default(), context)
+}
+
+pub(crate) fn write_block(_: Attributes, expr: &[TemplateExprNode], renderer: &Renderer, context: &RenderContext, out: &mut dyn fmt::Write) -> Result<(), RenderError> {
+    renderer.emit_multiple(expr.get(1..).unwrap_or_default(), context, out)
+}
+
+pub(crate) fn do_super(_: Attributes, _expr: &[TemplateExprNode], _renderer: &Renderer, _context: &RenderContext) -> Result<RenderValue, RenderError> {
+    // only meaningful inside a block overriding another, where it is replaced before rendering
+    Ok(RenderValue::Empty)
+}
+
+// the included template, which is in the include chain until the returned guard is dropped
+fn included_template(expr: &[TemplateExprNode], renderer: &Renderer, context: &RenderContext) -> Result<(Template, ScopeGuard), RenderError> {
+    let name = renderer.evaluate(expr.first()
+                                 .ok_or_else(|| RenderError::Include("template name not found".into()))?,
+                                 context)?
+        .into_text();
+
+    let chain = current_scope(|scope| scope.includes.clone());
+    if chain.contains(&name) {
+        return Err(RenderError::IncludeCycle(chain.into_iter().chain(std::iter::once(name)).collect()))
+    }
+
+    let template = renderer.load_template(&name)?;
+    Ok((template, enter_scope(|scope| scope.includes.push(name))))
+}
+
+pub(crate) fn do_include(_: Attributes, expr: &[TemplateExprNode], renderer: &Renderer, context: &RenderContext) -> Result<RenderValue, RenderError> {
+    let (template, _include) = included_template(expr, renderer, context)?;
+    renderer.evaluate(&template.expr, context)
+}
+
+pub(crate) fn write_include(_: Attributes, expr: &[TemplateExprNode], renderer: &Renderer, context: &RenderContext, out: &mut dyn fmt::Write) -> Result<(), RenderError> {
+    let (template, _include) = included_template(expr, renderer, context)?;
+    renderer.emit(&template.expr, context, out)
+}
+
+
+fn component_params(def: &TemplateTag) -> Result<Vec<String>, RenderError> {
+    match def.children.get(1) {
+        Some(TemplateExprNode::Tag(params)) if params.attrs.is_empty() => {
+            std::iter::once(&params.tag)
+                .filter(|p| !p.is_empty())
+                .map(|p| Ok(p.clone()))
+                .chain(params.children.iter()
+                       .map(|p| {
+                           p.as_identifier()
+                               .cloned()
+                               .ok_or_else(|| RenderError::Component("parameters must be identifiers".into()))
+                       }))
+                .collect()
         },
-        _ => Err(RenderError::Get("invalid index/indexable".into(), expr.to_vec()))
+        _ => Err(RenderError::Component("parameter list not found".into()))
     }
 }
+
+// declares the `defcomponent`s and `import`s in a list of expressions until the returned guard
+// is dropped
+pub(crate) fn declare_components(expr: &[TemplateExprNode], renderer: &Renderer, context: &RenderContext) -> Result<Option<ScopeGuard>, RenderError> {
+    let mut components = Vec::new();
+    let mut declare = |def: Rc<TemplateTag>| -> Result<(), RenderError> {
+        let name = def.children.first()
+            .and_then(TemplateExprNode::as_identifier)
+            .ok_or_else(|| RenderError::Component("component name not found".into()))?;
+        component_params(&def)?;
+        components.push((name.clone(), def));
+        Ok(())
+    };
+
+    for e in expr {
+        match e {
+            TemplateExprNode::Tag(tag) if tag.tag == "defcomponent" => declare(Rc::new(tag.clone()))?,
+            TemplateExprNode::Tag(tag) if tag.tag == "import" => {
+                let name = renderer.evaluate(tag.children.first()
+                                             .ok_or_else(|| RenderError::Component("import needs a template name".into()))?,
+                                             context)?
+                    .into_text();
+                for def in imported_components(&name, renderer)?.iter() {
+                    declare(def.clone())?;
+                }
+            },
+            _ => {}
+        }
+    }
+
+    if components.is_empty() {
+        return Ok(None)
+    }
+    Ok(Some(enter_scope(|scope| scope.components.extend(components))))
+}
+
+// the components an imported template defines, which is only loaded once in a render
+fn imported_components(name: &str, renderer: &Renderer) -> Result<Components, RenderError> {
+    let imports = current_scope(|scope| scope.imports.clone());
+    if let Some(components) = imports.borrow().get(name) {
+        return Ok(components.clone())
+    }
+    let template = renderer.load_template(name)?;
+    let components: Components = get_children_by_tag(&[template.expr], "defcomponent")
+        .into_iter()
+        .map(Rc::new)
+        .collect();
+    imports.borrow_mut().insert(name.into(), components.clone());
+    Ok(components)
+}
+
+pub(crate) fn find_component(name: &str) -> Option<Rc<TemplateTag>> {
+    current_scope(|scope| scope.components.get(name).cloned())
+}
+
+// the context a component's body is rendered in, with its parameters set. its children are
+// there for `(children)` until the returned guard is dropped
+fn component_scope(def: &TemplateTag, call: &TemplateTag, renderer: &Renderer, context: &RenderContext) -> Result<(RenderContext, ScopeGuard), RenderError> {
+    let params = component_params(def)?;
+
+    let mut scope = context.clone();
+    for param in &params {
+        scope.0.remove(param);
+    }
+    for attr in &call.attrs {
+        let name = renderer.evaluate(&attr.0, context)?.into_text();
+        if params.contains(&name) {
+            let value = match attr.1.as_slice() {
+                [] => RenderValue::Boolean(true),
+                [value] => renderer.evaluate(value, context)?,
+                values => renderer.evaluate_multiple(values, context)?,
+            };
+            scope.insert(name, value);
+        }
+    }
+    let children = Rc::new(renderer.evaluate_multiple(&call.children, context)?);
+    Ok((scope, enter_scope(|scope| scope.children = Some(children))))
+}
+
+pub(crate) fn call_component(def: &TemplateTag, call: &TemplateTag, renderer: &Renderer, context: &RenderContext) -> Result<RenderValue, RenderError> {
+    let (scope, _children) = component_scope(def, call, renderer, context)?;
+    renderer.evaluate_multiple(def.children.get(2..).unwrap_or_default(), &scope)
+}
+
+pub(crate) fn write_component(def: &TemplateTag, call: &TemplateTag, renderer: &Renderer, context: &RenderContext, out: &mut dyn fmt::Write) -> Result<(), RenderError> {
+    let (scope, _children) = component_scope(def, call, renderer, context)?;
+    renderer.emit_multiple(def.children.get(2..).unwrap_or_default(), &scope, out)
+}
+
+pub(crate) fn do_defcomponent(_: Attributes, _expr: &[TemplateExprNode], _renderer: &Renderer, _context: &RenderContext) -> Result<RenderValue, RenderError> {
+    // declared ahead of time by `declare_components`
+    Ok(RenderValue::Empty)
+}
+
+pub(crate) fn do_import(_: Attributes, _expr: &[TemplateExprNode], _renderer: &Renderer, _context: &RenderContext) -> Result<RenderValue, RenderError> {
+    // imported ahead of time by `declare_components`
+    Ok(RenderValue::Empty)
+}
+
+pub(crate) fn do_children(_: Attributes, _expr: &[TemplateExprNode], _renderer: &Renderer, _context: &RenderContext) -> Result<RenderValue, RenderError> {
+    Ok(current_scope(|scope| scope.children.as_deref().cloned())
+       .unwrap_or(RenderValue::Empty))
+}
+
+// the context the body of a `let` is evaluated in
+pub(crate) fn let_scope(expr: &[TemplateExprNode], renderer: &Renderer, context: &RenderContext) -> Result<RenderContext, RenderError> {
+    let bindings = match expr.first() {
+        Some(TemplateExprNode::Tag(bindings)) if bindings.tag.is_empty() => &bindings.children,
+        _ => return Err(RenderError::Let("expected a list of bindings".into()))
+    };
+
+    let mut scope = context.clone();
+    for binding in bindings {
+        let (name, value) = match binding {
+            TemplateExprNode::Tag(binding) if !binding.tag.is_empty() => (&binding.tag, &binding.children),
+            _ => return Err(RenderError::Let("expected a binding of the form (name value)".into()))
+        };
+        let value = match value.as_slice() {
+            [] => return Err(RenderError::Let(format!("`{}` is missing a value", name))),
+            [value] => renderer.evaluate(value, &scope)?,
+            values => renderer.evaluate_multiple(values, &scope)?,
+        };
+        scope.insert(name.clone(), value);
+    }
+    Ok(scope)
+}
+
+pub(crate) fn do_let(_: Attributes, expr: &[TemplateExprNode], renderer: &Renderer, context: &RenderContext) -> Result<RenderValue, RenderError> {
+    let scope = let_scope(expr, renderer, context)?;
+    renderer.evaluate_multiple(expr.get(1..).unwrap_or_default(), &scope)
+}
+
+pub(crate) fn write_let(_: Attributes, expr: &[TemplateExprNode], renderer: &Renderer, context: &RenderContext, out: &mut dyn fmt::Write) -> Result<(), RenderError> {
+    let scope = let_scope(expr, renderer, context)?;
+    renderer.emit_multiple(expr.get(1..).unwrap_or_default(), &scope, out)
+}
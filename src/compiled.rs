@@ -0,0 +1,366 @@
+use std::fmt;
+use std::sync::Arc;
+
+use crate::builtins;
+use crate::context::RenderContext;
+use crate::escape::escape_text;
+use crate::renderer::{Dialect, NodeHandler, Renderer, RenderError, expand_path, format_attribute, is_raw_text_element, is_void_element, write_element};
+use crate::template::{Span, Template, TemplateAttribute, TemplateExprNode, TemplateTag};
+
+
+/// a template prepared ahead of time by `Renderer::compile` for rendering many times:
+/// function handlers are looked up once, variable paths are split once and anything that
+/// does not depend on the context is rendered once into plain text.
+///
+/// the bodies of `for`, `if`, `when`, `unless`, `cond` and `let` are compiled the same way.
+/// other builtins and lists that define components are still evaluated from the template as
+/// usual.
+#[derive(Clone)]
+pub struct CompiledTemplate {
+    template: Template,
+    renderer_id: u64,
+    nodes: Vec<CompiledNode>,
+}
+
+impl CompiledTemplate {
+    /// the template this was compiled from
+    pub fn template(&self) -> &Template {
+        &self.template
+    }
+
+    pub(crate) fn renderer_id(&self) -> u64 {
+        self.renderer_id
+    }
+}
+
+#[derive(Clone)]
+enum CompiledNode {
+    /// output that is the same on every render, already escaped
+    Static(String),
+    Variable {
+        expr: String,
+        path: Vec<String>,
+        span: Span,
+    },
+    Function {
+        handler: Arc<NodeHandler>,
+        tag: TemplateTag,
+    },
+    /// a builtin that picks or repeats its compiled bodies
+    Builtin {
+        builtin: Builtin,
+        tag: TemplateTag,
+        bodies: Vec<Vec<CompiledNode>>,
+    },
+    Element {
+        tag: String,
+        attrs: Vec<CompiledAttribute>,
+        children: Vec<CompiledNode>,
+        span: Span,
+    },
+    /// evaluated the same as an uncompiled template, errors are placed at the span of the
+    /// tag the nodes belong to
+    Interpreted(Vec<TemplateExprNode>, Span),
+}
+
+// the builtins with compiled bodies and what those bodies are
+#[derive(Clone, Copy)]
+enum Builtin {
+    /// the true and false branches
+    If,
+    /// everything after the condition
+    When(bool),
+    /// the body of each clause
+    Cond,
+    /// everything after the bindings
+    Let,
+    /// everything after the iterable
+    For,
+}
+
+#[derive(Clone)]
+enum CompiledAttribute {
+    /// the attribute as it appears in the open tag, empty if it is omitted
+    Static(String),
+    Dynamic(TemplateAttribute),
+}
+
+
+pub(crate) fn compile(template: &Template, renderer: &Renderer) -> CompiledTemplate {
+    CompiledTemplate {
+        template: template.clone(),
+        renderer_id: renderer.id(),
+        nodes: merge_static(compile_node(&template.expr, renderer)),
+    }
+}
+
+// joins neighbouring static output into one string
+fn merge_static(nodes: Vec<CompiledNode>) -> Vec<CompiledNode> {
+    let mut merged = Vec::new();
+    for node in nodes {
+        match (merged.last_mut(), node) {
+            (_, CompiledNode::Static(text)) if text.is_empty() => {},
+            (Some(CompiledNode::Static(previous)), CompiledNode::Static(text)) => previous.push_str(&text),
+            (_, node) => merged.push(node),
+        }
+    }
+    merged
+}
+
+// nodes that evaluate to the same thing in any context
+fn is_static(expr: &TemplateExprNode) -> bool {
+    match expr {
+        TemplateExprNode::Identifier(ident, _) => !ident.starts_with('$'),
+        TemplateExprNode::String(..) | TemplateExprNode::Integer(..) | TemplateExprNode::Float(..) => true,
+        TemplateExprNode::Tag(_) => false,
+    }
+}
+
+fn compile_list(expr: &[TemplateExprNode], span: &Span, renderer: &Renderer) -> Vec<CompiledNode> {
+    // components declared in a list can replace any tag in it
+    let declares_components = expr.iter()
+        .any(|e| matches!(e, TemplateExprNode::Tag(tag) if tag.tag == "defcomponent" || tag.tag == "import"));
+    if declares_components {
+        return vec![CompiledNode::Interpreted(expr.to_vec(), span.clone())]
+    }
+    merge_static(expr.iter()
+                 .flat_map(|e| compile_node(e, renderer))
+                 .collect())
+}
+
+fn compile_node(expr: &TemplateExprNode, renderer: &Renderer) -> Vec<CompiledNode> {
+    match expr {
+        TemplateExprNode::Identifier(ident, span) => match ident.strip_prefix('$') {
+            Some(var) => vec![CompiledNode::Variable {
+                expr: ident.clone(),
+                path: var.split('.').map(String::from).collect(),
+                span: span.clone(),
+            }],
+            None => vec![CompiledNode::Static(escape_text(ident))],
+        },
+        TemplateExprNode::String(s, _) => vec![CompiledNode::Static(escape_text(s))],
+        TemplateExprNode::Integer(i, _) => vec![CompiledNode::Static(i.to_string())],
+        TemplateExprNode::Float(f, _) => vec![CompiledNode::Static(f.to_string())],
+        TemplateExprNode::Tag(tag) if tag.tag.is_empty() && tag.children.is_empty() => Vec::new(),
+        // left to the renderer to report
+        TemplateExprNode::Tag(tag) if tag.tag.is_empty() => vec![interpreted(tag)],
+        TemplateExprNode::Tag(tag) => compile_tag(tag, renderer),
+    }
+}
+
+fn compile_tag(tag: &TemplateTag, renderer: &Renderer) -> Vec<CompiledNode> {
+    // anything the sandbox rejects is left to the renderer to report when it is rendered
+    if let Some(sandbox) = renderer.sandbox() {
+        let allowed = match renderer.handler(&tag.tag) {
+            Some(_) => sandbox.check_function(&tag.tag),
+            None => sandbox.check_tag(&tag.tag),
+        };
+        if allowed.is_err() {
+            return vec![interpreted(tag)]
+        }
+    }
+
+    let prolog = CompiledNode::Static(renderer.dialect().prolog().into());
+    match tag.tag.as_str() {
+        "document" if renderer.is_streaming("document") && tag.attrs.is_empty() => {
+            std::iter::once(prolog)
+                .chain(compile_list(&tag.children, &tag.span, renderer))
+                .collect()
+        },
+        "html" if renderer.is_streaming("html") => {
+            let mut html = tag.clone();
+            if renderer.dialect() == Dialect::Xhtml {
+                // whether `xmlns` needs adding is only known ahead of time for static attributes
+                let attrs = match static_attributes(&tag.tag, &tag.attrs, renderer) {
+                    Some(attrs) => attrs,
+                    None => return vec![interpreted(tag)],
+                };
+                if !attrs.iter().any(|attr| attr.starts_with(" xmlns=")) {
+                    html.attrs.push(TemplateAttribute(TemplateExprNode::String("xmlns".into(), Span::default()),
+                                                      vec![TemplateExprNode::String("http://www.w3.org/1999/xhtml".into(), Span::default())]));
+                }
+            }
+            std::iter::once(prolog)
+                .chain(compile_element(&html, renderer))
+                .collect()
+        },
+        // only while they are the builtins, the same as when streaming
+        "if" | "when" | "unless" | "cond" | "let" | "with" | "for" if renderer.is_streaming(&tag.tag) => {
+            vec![compile_builtin(tag, renderer)]
+        },
+        _ => match renderer.handler(&tag.tag) {
+            Some(handler) => vec![CompiledNode::Function {
+                handler: handler.clone(),
+                tag: tag.clone(),
+            }],
+            None => compile_element(tag, renderer),
+        },
+    }
+}
+
+fn compile_builtin(tag: &TemplateTag, renderer: &Renderer) -> CompiledNode {
+    let expr = &tag.children;
+    let compile_branch = |i: usize| expr.get(i)
+        .map(|e| compile_node(e, renderer))
+        .unwrap_or_default();
+    let (builtin, bodies) = match tag.tag.as_str() {
+        "if" => (Builtin::If, vec![compile_branch(1), compile_branch(2)]),
+        "when" => (Builtin::When(true), vec![compile_list(expr.get(1..).unwrap_or_default(), &tag.span, renderer)]),
+        "unless" => (Builtin::When(false), vec![compile_list(expr.get(1..).unwrap_or_default(), &tag.span, renderer)]),
+        "cond" => (Builtin::Cond, expr.iter()
+                   .map(|clause| compile_list(builtins::cond_clause_body(clause), &tag.span, renderer))
+                   .collect()),
+        "for" => (Builtin::For, vec![compile_list(builtins::for_body(expr), &tag.span, renderer)]),
+        _ => (Builtin::Let, vec![compile_list(expr.get(1..).unwrap_or_default(), &tag.span, renderer)]),
+    };
+    CompiledNode::Builtin {
+        builtin,
+        tag: tag.clone(),
+        bodies,
+    }
+}
+
+// the text of each attribute if none of them depend on the context
+fn static_attributes(tag: &str, attrs: &[TemplateAttribute], renderer: &Renderer) -> Option<Vec<String>> {
+    attrs.iter()
+        .map(|attr| static_attribute(tag, attr, renderer))
+        .collect()
+}
+
+// attributes the sandbox rejects are left to be checked again when rendered
+fn static_attribute(tag: &str, attr: &TemplateAttribute, renderer: &Renderer) -> Option<String> {
+    if !is_static(&attr.0) || !attr.1.iter().all(is_static) {
+        return None
+    }
+    let attr = renderer.evaluate_attribute(attr, &RenderContext::default()).ok()?;
+    if let (Some(sandbox), Some(attr)) = (renderer.sandbox(), &attr) {
+        sandbox.check_attribute(tag, attr).ok()?;
+    }
+    Some(attr
+         .map(|attr| format_attribute(&attr, renderer.dialect()))
+         .unwrap_or_default())
+}
+
+fn interpreted(tag: &TemplateTag) -> CompiledNode {
+    CompiledNode::Interpreted(vec![TemplateExprNode::Tag(tag.clone())], tag.span.clone())
+}
+
+fn compile_element(tag: &TemplateTag, renderer: &Renderer) -> Vec<CompiledNode> {
+    // left to the renderer so that its checks happen when the element is rendered
+    if renderer.dialect() != Dialect::Xml && (is_raw_text_element(&tag.tag) || (is_void_element(&tag.tag) && !tag.children.is_empty())) {
+        return vec![interpreted(tag)]
+    }
+    if renderer.sandbox().is_some_and(|sandbox| sandbox.check_tag(&tag.tag).is_err()) {
+        return vec![interpreted(tag)]
+    }
+
+    let element = CompiledNode::Element {
+        tag: tag.tag.clone(),
+        attrs: tag.attrs.iter()
+            .map(|attr| match static_attribute(&tag.tag, attr, renderer) {
+                Some(text) => CompiledAttribute::Static(text),
+                None => CompiledAttribute::Dynamic(attr.clone()),
+            })
+            .collect(),
+        children: compile_list(&tag.children, &tag.span, renderer),
+        span: tag.span.clone(),
+    };
+
+    if let CompiledNode::Element { attrs, children, .. } = &element {
+        let is_static = attrs.iter().all(|attr| matches!(attr, CompiledAttribute::Static(_)))
+            && children.iter().all(|child| matches!(child, CompiledNode::Static(_)));
+        let mut text = String::new();
+        if is_static && emit_node(&element, renderer, &RenderContext::default(), &mut text).is_ok() {
+            return vec![CompiledNode::Static(text)]
+        }
+    }
+    vec![element]
+}
+
+
+pub(crate) fn emit(template: &CompiledTemplate, renderer: &Renderer, context: &RenderContext, out: &mut dyn fmt::Write) -> Result<(), RenderError> {
+    emit_nodes(&template.nodes, renderer, context, out)
+}
+
+fn emit_nodes(nodes: &[CompiledNode], renderer: &Renderer, context: &RenderContext, out: &mut dyn fmt::Write) -> Result<(), RenderError> {
+    nodes.iter()
+        .try_for_each(|node| emit_node(node, renderer, context, out))
+}
+
+fn emit_node(node: &CompiledNode, renderer: &Renderer, context: &RenderContext, out: &mut dyn fmt::Write) -> Result<(), RenderError> {
+    let _nesting = match node {
+        CompiledNode::Static(text) => return Ok(out.write_str(text)?),
+        _ => renderer.step()?,
+    };
+    match node {
+        CompiledNode::Static(_) => Ok(()),
+        CompiledNode::Variable { expr, path, span } => {
+            expand_path(expr, path, renderer, context)
+                .and_then(|value| Ok(value.write_to(out)?))
+                .map_err(|err| err.at(span))
+        },
+        CompiledNode::Function { handler, tag } => {
+            renderer.evaluate_attrs(&tag.attrs, context)
+                .and_then(|attrs| handler(attrs, &tag.children, renderer, context))
+                .and_then(|value| Ok(value.write_to(out)?))
+                .map_err(|err| err.at(&tag.span))
+        },
+        CompiledNode::Builtin { builtin, tag, bodies } => {
+            renderer.evaluate_attrs(&tag.attrs, context)
+                .and_then(|_| emit_builtin(*builtin, &tag.children, bodies, renderer, context, out))
+                .map_err(|err| err.at(&tag.span))
+        },
+        CompiledNode::Element { tag, attrs, children, span } => {
+            emit_element(tag, attrs, children, renderer, context, out)
+                .map_err(|err| err.at(span))
+        },
+        CompiledNode::Interpreted(expr, span) => {
+            renderer.emit_multiple(expr, context, out)
+                .map_err(|err| err.at(span))
+        },
+    }
+}
+
+// the same as the builtin's streaming handler, with the compiled bodies in place of the
+// template's
+fn emit_builtin(builtin: Builtin, expr: &[TemplateExprNode], bodies: &[Vec<CompiledNode>], renderer: &Renderer, context: &RenderContext, out: &mut dyn fmt::Write) -> Result<(), RenderError> {
+    match builtin {
+        Builtin::If => match builtins::if_branch(expr, renderer, context)? {
+            Some(branch) => emit_nodes(&bodies[branch - 1], renderer, context, out),
+            None => Ok(()),
+        },
+        Builtin::When(expected) => {
+            if builtins::when_matches(expr, renderer, context, expected)? {
+                emit_nodes(&bodies[0], renderer, context, out)?;
+            }
+            Ok(())
+        },
+        Builtin::Cond => match builtins::cond_clause(expr, renderer, context)? {
+            Some(clause) => emit_nodes(&bodies[clause], renderer, context, out),
+            None => Ok(()),
+        },
+        Builtin::Let => {
+            let scope = builtins::let_scope(expr, renderer, context)?;
+            emit_nodes(&bodies[0], renderer, &scope, out)
+        },
+        Builtin::For => builtins::for_each(expr, renderer, context, |context| emit_nodes(&bodies[0], renderer, context, out)),
+    }
+}
+
+fn emit_element(tag: &str, attrs: &[CompiledAttribute], children: &[CompiledNode], renderer: &Renderer, context: &RenderContext, out: &mut dyn fmt::Write) -> Result<(), RenderError> {
+    let mut attr_str = String::new();
+    for attr in attrs {
+        match attr {
+            CompiledAttribute::Static(text) => attr_str.push_str(text),
+            CompiledAttribute::Dynamic(attr) => {
+                if let Some(attr) = renderer.evaluate_attribute(attr, context)? {
+                    if let Some(sandbox) = renderer.sandbox() {
+                        sandbox.check_attribute(tag, &attr)?;
+                    }
+                    attr_str.push_str(&format_attribute(&attr, renderer.dialect()));
+                }
+            },
+        }
+    }
+    write_element(tag, &attr_str, renderer.dialect(), out, |out| emit_nodes(children, renderer, context, out))
+}
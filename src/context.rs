@@ -6,11 +6,16 @@ use crate::template::Template;
 #[derive(Clone, Debug)]
 pub enum ContextValue {
     Integer(i64),
+    Float(f64),
     Boolean(bool),
     String(String),
+    /// markup that is inserted into the output without escaping
+    Safe(String),
     Vec(Vec<ContextValue>),
     Object(RenderContext),
     Template(Template),
+    /// a value that is set but empty, unlike a key that is not set at all
+    Null,
 }
 
 impl From<&str> for ContextValue {
@@ -61,6 +66,15 @@ impl From<Template> for ContextValue {
     }
 }
 
+impl<T: Into<ContextValue>> From<Option<T>> for ContextValue {
+    fn from(other: Option<T>) -> Self {
+        match other {
+            Some(value) => value.into(),
+            None => ContextValue::Null,
+        }
+    }
+}
+
 impl From<bool> for ContextValue {
     fn from(other: bool) -> Self {
         ContextValue::Boolean(other)
@@ -92,13 +106,30 @@ impl From<u32> for ContextValue {
     }
 }
 
+impl From<f32> for ContextValue {
+    fn from(other: f32) -> Self {
+        ContextValue::Float(other as f64)
+    }
+}
+
+impl From<f64> for ContextValue {
+    fn from(other: f64) -> Self {
+        ContextValue::Float(other)
+    }
+}
+
 impl PartialEq for ContextValue {
     fn eq(&self, other: &ContextValue) -> bool {
         match (self, other) {
             (ContextValue::Integer(a), ContextValue::Integer(b)) => a == b,
+            (ContextValue::Float(a), ContextValue::Float(b)) => a == b,
+            (ContextValue::Integer(a), ContextValue::Float(b)) => (*a as f64) == *b,
+            (ContextValue::Float(a), ContextValue::Integer(b)) => *a == (*b as f64),
             (ContextValue::Boolean(a), ContextValue::Boolean(b)) => a == b,
             (ContextValue::String(a), ContextValue::String(b)) => a == b,
+            (ContextValue::Safe(a), ContextValue::Safe(b)) => a == b,
             (ContextValue::Vec(a), ContextValue::Vec(b)) => a == b,
+            (ContextValue::Null, ContextValue::Null) => true,
             _ => false,
         }
     }
@@ -108,8 +139,12 @@ impl PartialOrd for ContextValue {
     fn partial_cmp(&self, other: &ContextValue) -> Option<std::cmp::Ordering> {
         match (self, other) {
             (ContextValue::Integer(a), ContextValue::Integer(b)) => a.partial_cmp(b),
+            (ContextValue::Float(a), ContextValue::Float(b)) => a.partial_cmp(b),
+            (ContextValue::Integer(a), ContextValue::Float(b)) => (*a as f64).partial_cmp(b),
+            (ContextValue::Float(a), ContextValue::Integer(b)) => a.partial_cmp(&(*b as f64)),
             (ContextValue::Boolean(a), ContextValue::Boolean(b)) => a.partial_cmp(b),
             (ContextValue::String(a), ContextValue::String(b)) => a.partial_cmp(b),
+            (ContextValue::Safe(a), ContextValue::Safe(b)) => a.partial_cmp(b),
             (ContextValue::Vec(a), ContextValue::Vec(b)) => a.partial_cmp(b),
             _ => None,
         }
@@ -120,12 +155,14 @@ impl From<&RenderValue> for ContextValue {
     fn from(other: &RenderValue) -> ContextValue {
         match other {
             RenderValue::String(s) => ContextValue::String(s.clone()),
+            RenderValue::Safe(s) => ContextValue::Safe(s.clone()),
             RenderValue::Integer(i) => ContextValue::Integer(*i),
+            RenderValue::Float(f) => ContextValue::Float(*f),
             RenderValue::Boolean(b) => ContextValue::Boolean(*b),
             RenderValue::Vec(v) => ContextValue::Vec(v.iter().map(|e| e.into()).collect()),
             RenderValue::Object(o) => ContextValue::Object(RenderContext(o.iter().map(|(k, v)| (k.clone(), v.into())).collect())),
             RenderValue::Template(t) => ContextValue::Template(t.clone()),
-            RenderValue::Empty => ContextValue::String("".into()),
+            RenderValue::Null | RenderValue::Empty => ContextValue::Null,
         }
     }
 }
@@ -133,13 +170,15 @@ impl From<&RenderValue> for ContextValue {
 impl From<RenderValue> for ContextValue {
     fn from(other: RenderValue) -> ContextValue {
         match other {
-            RenderValue::String(s) => ContextValue::String(s.clone()),
+            RenderValue::String(s) => ContextValue::String(s),
+            RenderValue::Safe(s) => ContextValue::Safe(s),
             RenderValue::Integer(i) => ContextValue::Integer(i),
+            RenderValue::Float(f) => ContextValue::Float(f),
             RenderValue::Boolean(b) => ContextValue::Boolean(b),
             RenderValue::Vec(v) => ContextValue::Vec(v.iter().map(|e| e.into()).collect()),
             RenderValue::Object(o) => ContextValue::Object(RenderContext(o.iter().map(|(k, v)| (k.clone(), v.into())).collect())),
             RenderValue::Template(t) => ContextValue::Template(t),
-            RenderValue::Empty => ContextValue::String("".into()),
+            RenderValue::Null | RenderValue::Empty => ContextValue::Null,
         }
     }
 }
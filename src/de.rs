@@ -0,0 +1,211 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+
+use serde::de::{self, Deserialize, Deserializer, MapAccess, SeqAccess, Visitor};
+
+use crate::context::{ContextValue, RenderContext};
+
+
+#[derive(thiserror::Error, Debug)]
+pub enum ContextError {
+    #[error("could not read `{0}`: {1}")]
+    Io(String, std::io::Error),
+    #[error("no context format for the extension of `{0}`")]
+    UnknownFormat(String),
+    #[error("top level value is not an object")]
+    NotAnObject,
+    #[cfg(feature = "json")]
+    #[error("json error: {0}")]
+    Json(#[from] serde_json::Error),
+    #[cfg(feature = "toml")]
+    #[error("toml error: {0}")]
+    Toml(#[from] toml::de::Error),
+    #[cfg(feature = "toml")]
+    #[error("toml error: {0}")]
+    TomlSerialize(#[from] toml::ser::Error),
+    #[cfg(feature = "yaml")]
+    #[error("yaml error: {0}")]
+    Yaml(#[from] serde_yaml::Error),
+}
+
+
+// toml hands datetimes to deserializers as a map with this one key
+const TOML_DATETIME_KEY: &str = "$__toml_private_datetime";
+
+struct ContextValueVisitor;
+
+impl<'de> Visitor<'de> for ContextValueVisitor {
+    type Value = Option<ContextValue>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a boolean, number, string, array or object")
+    }
+
+    fn visit_bool<E: de::Error>(self, v: bool) -> Result<Self::Value, E> {
+        Ok(Some(ContextValue::Boolean(v)))
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<Self::Value, E> {
+        Ok(Some(ContextValue::Integer(v)))
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<Self::Value, E> {
+        i64::try_from(v)
+            .map(|i| Some(ContextValue::Integer(i)))
+            .map_err(|_| E::custom(format!("integer {} does not fit in an i64", v)))
+    }
+
+    fn visit_f64<E: de::Error>(self, v: f64) -> Result<Self::Value, E> {
+        Ok(Some(ContextValue::Float(v)))
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+        Ok(Some(ContextValue::String(v.into())))
+    }
+
+    fn visit_string<E: de::Error>(self, v: String) -> Result<Self::Value, E> {
+        Ok(Some(ContextValue::String(v)))
+    }
+
+    fn visit_none<E: de::Error>(self) -> Result<Self::Value, E> {
+        Ok(None)
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<Self::Value, E> {
+        Ok(None)
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_any(self)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut values = Vec::new();
+        while let Some(value) = seq.next_element::<ContextValue>()? {
+            values.push(value);
+        }
+        Ok(Some(ContextValue::Vec(values)))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut object = BTreeMap::new();
+        while let Some(key) = map.next_key::<ContextValue>()? {
+            let key = match key {
+                ContextValue::String(s) => s,
+                ContextValue::Integer(i) => i.to_string(),
+                ContextValue::Boolean(b) => b.to_string(),
+                _ => return Err(de::Error::custom("object keys must be strings")),
+            };
+            if key == TOML_DATETIME_KEY {
+                return Ok(Some(map.next_value::<ContextValue>()?))
+            }
+            // null values leave the key unset, the same as `None` fields when serializing
+            if let Some(value) = map.next_value::<NullableValue>()?.0 {
+                object.insert(key, value);
+            }
+        }
+        Ok(Some(ContextValue::Object(RenderContext(object))))
+    }
+}
+
+struct NullableValue(Option<ContextValue>);
+
+impl<'de> Deserialize<'de> for NullableValue {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(NullableValue(deserializer.deserialize_any(ContextValueVisitor)?))
+    }
+}
+
+impl<'de> Deserialize<'de> for ContextValue {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(deserializer.deserialize_any(ContextValueVisitor)?
+           .unwrap_or(ContextValue::Null))
+    }
+}
+
+impl<'de> Deserialize<'de> for RenderContext {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        match ContextValue::deserialize(deserializer)? {
+            ContextValue::Object(context) => Ok(context),
+            _ => Err(de::Error::custom(ContextError::NotAnObject)),
+        }
+    }
+}
+
+
+#[cfg(any(feature = "json", feature = "toml", feature = "yaml"))]
+fn into_context(value: ContextValue) -> Result<RenderContext, ContextError> {
+    match value {
+        ContextValue::Object(context) => Ok(context),
+        _ => Err(ContextError::NotAnObject),
+    }
+}
+
+#[cfg(any(feature = "json", feature = "toml", feature = "yaml"))]
+fn read(path: &Path) -> Result<String, ContextError> {
+    std::fs::read_to_string(path)
+        .map_err(|err| ContextError::Io(path.display().to_string(), err))
+}
+
+impl RenderContext {
+    /// loads a context from a file, choosing the format by its extension
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<RenderContext, ContextError> {
+        let path = path.as_ref();
+        match path.extension().and_then(|e| e.to_str()) {
+            #[cfg(feature = "json")]
+            Some("json") => RenderContext::from_json_path(path),
+            #[cfg(feature = "toml")]
+            Some("toml") => RenderContext::from_toml_path(path),
+            #[cfg(feature = "yaml")]
+            Some("yaml" | "yml") => RenderContext::from_yaml_path(path),
+            _ => Err(ContextError::UnknownFormat(path.display().to_string())),
+        }
+    }
+}
+
+#[cfg(feature = "json")]
+impl RenderContext {
+    pub fn from_json_str(json: &str) -> Result<RenderContext, ContextError> {
+        into_context(serde_json::from_str(json)?)
+    }
+
+    pub fn from_json_path<P: AsRef<Path>>(path: P) -> Result<RenderContext, ContextError> {
+        RenderContext::from_json_str(&read(path.as_ref())?)
+    }
+
+    /// pretty printed json of every variable in the context
+    pub fn to_json_string(&self) -> Result<String, ContextError> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+}
+
+#[cfg(feature = "toml")]
+impl RenderContext {
+    pub fn from_toml_str(toml: &str) -> Result<RenderContext, ContextError> {
+        into_context(toml::from_str(toml)?)
+    }
+
+    pub fn from_toml_path<P: AsRef<Path>>(path: P) -> Result<RenderContext, ContextError> {
+        RenderContext::from_toml_str(&read(path.as_ref())?)
+    }
+
+    pub fn to_toml_string(&self) -> Result<String, ContextError> {
+        Ok(toml::to_string(self)?)
+    }
+}
+
+#[cfg(feature = "yaml")]
+impl RenderContext {
+    pub fn from_yaml_str(yaml: &str) -> Result<RenderContext, ContextError> {
+        into_context(serde_yaml::from_str(yaml)?)
+    }
+
+    pub fn from_yaml_path<P: AsRef<Path>>(path: P) -> Result<RenderContext, ContextError> {
+        RenderContext::from_yaml_str(&read(path.as_ref())?)
+    }
+
+    pub fn to_yaml_string(&self) -> Result<String, ContextError> {
+        Ok(serde_yaml::to_string(self)?)
+    }
+}
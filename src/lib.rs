@@ -9,19 +9,113 @@ an s-expression based html templating system.
   (title "basic example")))
 ```
 
+## comments and strings
+`;` comments out the rest of a line and `#| ... |#` comments out a block, which may be
+nested. quoted strings understand `\"`, `\\`, `\n`, `\r`, `\t`, `\0` and `\u{...}` escapes.
+```sato
+; the page header
+(div #| (span "removed") |#
+ (p "a \"quoted\" word" 1.5))
+```
+
 ## tag attributes
 ```sato
 (html
  (head (@ (some thing))
   (title "basic example")))
 ```
+attribute values are escaped. names can come from variables too, but a name with
+whitespace, quotes, `<`, `>`, `/` or `=` in it is an `InvalidAttributeName` error.
+
+## boolean attributes
+an attribute without a value is rendered bare, and an attribute whose value is a variable
+set to `false`, empty or an unset variable is left out entirely. a bare `false` written in
+the template is text like any other symbol, for attributes like `draggable` that take it as
+a value.
+```sato
+(html
+ (body
+  (input (@ (type checkbox) (disabled) (checked $is_checked)))))
+```
+
+## void and raw text elements
+void elements like `br`, `img` and `input` are rendered without a closing tag and cannot
+have children, every other element always gets a closing tag. the contents of `script`
+and `style` are not escaped, but they may not contain their own closing tag.
+```sato
+(html
+ (head
+  (style "body > div { color: red; }"))
+ (body
+  (div)
+  (br)))
+```
 
 ## variables
-variables in sato are prefixed with a `$`.
+variables in sato are prefixed with a `$`. quoted text is always literal, so `"$5 off"`
+is never looked up as a variable.
 ```sato
 (html
  (head
-  (title $some_variable)))
+  (title $some_variable " - " "$5 off")))
+```
+
+## escaping
+text and attribute values are html escaped when rendered. `raw` marks its contents as
+trusted markup that is inserted as-is.
+```sato
+(html
+ (body
+  (div $user_comment)
+  (div (raw $trusted_markup))))
+```
+
+## template inheritance
+a template can extend another template registered with `RendererBuilder::template`, replacing any of its
+`block`s. `(super)` inside a replacement block inserts the content of the block it replaces.
+```sato
+(html
+ (head (title (block title "my site")))
+ (body (block content)))
+```
+```sato
+(extends "base.sato"
+ (block title "a page - " (super))
+ (block content
+  (div "page content")))
+```
+
+## includes
+```sato
+(html
+ (body
+  (include "partials/nav.sato")
+  (div "page content")))
+```
+
+## components
+components can be defined inside a template. attributes listed as parameters are bound as
+variables and `(children)` renders whatever the component was called with. definitions are
+visible to the expressions next to them, and `import` brings in the ones from another template.
+```sato
+(html
+ (import "components.sato")
+ (defcomponent card (title)
+  (div (@ (class card))
+   (h2 $title)
+   (children)))
+ (body
+  (card (@ (title "hello"))
+   (p "card body"))))
+```
+
+## local variables
+```sato
+(html
+ (body
+  (let ((author (get $post author))
+        (name $author.name))
+   (div "posted by " $name))))
 ```
 
 ## conditionals
@@ -29,10 +123,22 @@ variables in sato are prefixed with a `$`.
 (html
  (head
   (title (if (is-set $some_variable)
-             (div "$some_variable")
+             (div $some_variable)
              (div "variable is not set")))))
 ```
 
+## multiple conditions
+```sato
+(html
+ (body
+  (cond ((eq $role admin) (div "admin"))
+        ((eq $role editor) (div "editor"))
+        (else (div "reader")))
+  (when (is-set $notice)
+   (div "notice:")
+   (div $notice))))
+```
+
 ## iteration over arrays
 ```sato
 (html
@@ -75,7 +181,7 @@ let expr = r#"(html (head (title "basic example")))"#;
 let template = Template::from_str(expr).unwrap();
 let html = renderer.render(&template, &RenderContext::default()).unwrap();
 
-assert_eq!(html, "<!doctype html5><html><head><title>basic example</title></head></html>")
+assert_eq!(html, "<!DOCTYPE html><html><head><title>basic example</title></head></html>")
 ```
 
 # using variables
@@ -94,12 +200,228 @@ let context = RenderContext::builder()
     .build();
 let html = renderer.render(&template, &context).unwrap();
 
-assert_eq!(html, "<!doctype html5><html><body><div>zxc</div><div>xcv</div><div>cvb</div></body></html>")
+assert_eq!(html, "<!DOCTYPE html><html><body><div>zxc</div><div>xcv</div><div>cvb</div></body></html>")
+```
+
+# output dialects
+the same templates can produce html5, xhtml or generic xml such as feeds and sitemaps.
+```rust
+use sato::renderer::{Dialect, Renderer};
+use sato::context::RenderContext;
+use sato::template::Template;
+
+let renderer = Renderer::builder()
+    .dialect(Dialect::Xml)
+    .build();
+let expr = r#"(document (urlset (url (loc "https://example.com/")) (url (loc))))"#;
+let template = Template::from_str(expr).unwrap();
+let xml = renderer.render(&template, &RenderContext::default()).unwrap();
+
+assert_eq!(xml, r#"<?xml version="1.0" encoding="UTF-8"?><urlset><url><loc>https://example.com/</loc></url><url><loc /></url></urlset>"#)
+```
+
+# template loaders
+templates used by `include` and `extends` are found through a `TemplateLoader`.
+```rust
+use std::collections::HashMap;
+use sato::renderer::Renderer;
+use sato::context::RenderContext;
+use sato::template::Template;
+
+let mut templates = HashMap::new();
+templates.insert("nav.sato".to_string(), Template::from_str(r#"(nav (a (@ (href "/")) "home"))"#).unwrap());
+
+let renderer = Renderer::builder()
+    .loader(Box::new(templates))
+    .build();
+let template = Template::from_str(r#"(html (body (include "nav.sato")))"#).unwrap();
+let html = renderer.render(&template, &RenderContext::default()).unwrap();
+
+assert_eq!(html, r#"<!DOCTYPE html><html><body><nav><a href="/">home</a></nav></body></html>"#)
+```
+`FileSystemLoader::new("templates")` loads them from files in a directory instead.
+
+# streaming output
+`render_to_writer` writes to a file, socket or response body while the template is still
+being evaluated instead of building the whole page in memory first, `render_to_fmt` does the
+same for a `fmt::Write`. elements, components, `for`, `if` and the other builtins that
+choose or repeat part of the template write as they go, while a custom function's result is
+written once it returns. if rendering fails part of the output may already have been written.
+```rust
+use std::io::Write;
+use sato::renderer::Renderer;
+use sato::context::RenderContext;
+use sato::template::Template;
+
+let template = Template::from_str(r#"(html (body (for i in $items (p $i))))"#).unwrap();
+let context = RenderContext::builder()
+    .insert("items", vec!["a", "b"])
+    .build();
+let mut out = std::io::BufWriter::new(Vec::new());
+Renderer::default().render_to_writer(&template, &context, &mut out).unwrap();
+out.flush().unwrap();
+
+assert_eq!(out.get_ref().as_slice(), b"<!DOCTYPE html><html><body><p>a</p><p>b</p></body></html>")
+```
+
+# compiled templates
+a template that is rendered over and over can be compiled once by the renderer that will
+render it. handlers are looked up, variable paths split and markup that does not depend on
+the context rendered ahead of time, so each render only does the parts that can change.
+```rust
+use sato::renderer::Renderer;
+use sato::context::RenderContext;
+use sato::template::Template;
+
+let renderer = Renderer::default();
+let template = Template::from_str(r#"(html (head (title "users")) (body (p $user.name)))"#).unwrap();
+let compiled = renderer.compile(&template);
+
+for name in ["jake", "sato"] {
+    let context = RenderContext::builder()
+        .insert("user", RenderContext::builder().insert("name", name).build())
+        .build();
+    let html = renderer.render_compiled(&compiled, &context).unwrap();
+    assert_eq!(html, renderer.render(&template, &context).unwrap());
+}
+```
+
+# contexts from serde
+with the `serde` feature enabled, anything that implements `Serialize` can be turned into a
+context. struct fields and map entries that are `None` are left unset.
+```rust,ignore
+use serde::Serialize;
+use sato::context::RenderContext;
+
+#[derive(Serialize)]
+struct Post {
+    title: String,
+    subtitle: Option<String>,
+    tags: Vec<String>,
+}
+
+let post = Post {
+    title: "hello".into(),
+    subtitle: None,
+    tags: vec!["rust".into()],
+};
+let context = RenderContext::from_serialize(&post).unwrap();
+assert!(context.get("subtitle").is_none());
+```
+
+# contexts from data files
+the `json`, `toml` and `yaml` features add constructors that read a context from those
+formats, and `to_json_string` and friends to write one back out when debugging.
+`RenderContext::from_path` picks the format from the file extension.
+```rust,ignore
+use sato::context::RenderContext;
+
+let context = RenderContext::from_toml_str(r#"
+[site]
+title = "my site"
+"#).unwrap();
+let context = RenderContext::from_path("data/posts.json").unwrap();
+println!("{}", context.to_json_string().unwrap());
+```
+
+# strict mode
+by default an unset variable renders as its own name. a strict renderer makes it an error
+instead, `is-set` and `default` are the ways to handle a value that may be missing.
+```rust
+use sato::renderer::{Renderer, RenderError};
+use sato::context::RenderContext;
+use sato::template::Template;
+
+let renderer = Renderer::builder()
+    .strict(true)
+    .build();
+let context = RenderContext::builder()
+    .insert("user", RenderContext::builder().insert("name", "jake").build())
+    .build();
+
+let template = Template::from_str(r#"(div (default $user.nickname $user.name))"#).unwrap();
+assert_eq!(renderer.render(&template, &context).unwrap(), "<div>jake</div>");
+
+let template = Template::from_str(r#"(div $user.nickname)"#).unwrap();
+let err = renderer.render(&template, &context).unwrap_err();
+assert!(matches!(err.root(), RenderError::UndefinedVariable(path) if path == "user.nickname"));
+```
+
+# resource limits
+templates written by people you do not trust can be given limits on how deeply they nest,
+how much they output, how long a single loop can run and how many expressions a whole
+render evaluates, where every iteration of a loop counts as one too. each limit has its own
+error. only nesting is limited by default, to 128 levels, which keeps recursive components
+and templates from overflowing the stack.
+```rust
+use sato::renderer::{Renderer, RenderError};
+use sato::context::RenderContext;
+use sato::template::Template;
+
+let renderer = Renderer::builder()
+    .max_depth(64)
+    .max_output(1024 * 1024)
+    .max_iterations(1000)
+    .fuel(100_000)
+    .build();
+
+let template = Template::from_str(r#"(ul (for i in (range 0 1000000000) (li $i)))"#).unwrap();
+let err = renderer.render(&template, &RenderContext::default()).unwrap_err();
+assert!(matches!(err.root(), RenderError::IterationLimit(1000)));
+```
+
+# sandboxing
+a sandbox limits templates to the builtins, tags and attributes it allows, anything else is
+an error when the template is rendered. `Sandbox::user_content` allows the builtins and
+markup that are safe for content written by users, without `raw`, `include` or loading
+other templates. event handler attributes like `onclick` and `javascript:` urls are
+rejected whatever the sandbox allows.
+```rust
+use sato::renderer::{Renderer, RenderError};
+use sato::context::RenderContext;
+use sato::sandbox::Sandbox;
+use sato::template::Template;
+
+let renderer = Renderer::builder()
+    .sandbox(Sandbox::user_content().allow_tags(["video"]).allow_attributes(["controls"]))
+    .build();
+
+let template = Template::from_str(r#"(p (script "alert(1)"))"#).unwrap();
+let err = renderer.render(&template, &RenderContext::default()).unwrap_err();
+assert!(matches!(err.root(), RenderError::TagNotAllowed(tag) if tag == "script"));
+
+let template = Template::from_str(r#"(a (@ (href "javascript:alert(1)")) "click")"#).unwrap();
+let err = renderer.render(&template, &RenderContext::default()).unwrap_err();
+assert!(matches!(err.root(), RenderError::UrlNotAllowed(..)));
+```
+
+# error locations
+parse and render errors point at the place in the template they came from. templates
+loaded from a path or a `TemplateLoader` are named after their file, others can be named
+with `Template::from_named_str`.
+```rust
+use sato::renderer::Renderer;
+use sato::context::RenderContext;
+use sato::template::Template;
+
+let template = Template::from_named_str("page.sato", "(div\n  (get $items 7))").unwrap();
+let context = RenderContext::builder()
+    .insert("items", vec!["a", "b"])
+    .build();
+let err = Renderer::default().render(&template, &context).unwrap_err();
+let span = err.span().unwrap();
+
+assert_eq!((span.file(), span.line(), span.column()), (Some("page.sato"), 2, 3));
+assert_eq!(err.to_string(), "error in `get`: array out of bounds
+ --> page.sato:2:3
+  |
+2 |   (get $items 7))
+  |   ^")
 ```
 
 # custom handler functions
 ```rust
-use sato::renderer::{Attributes, Renderer, RenderError};
+use sato::renderer::{Attributes, Renderer, RenderError, RenderValue};
 use sato::context::RenderContext;
 use sato::template::{Template, TemplateExprNode};
 
@@ -116,7 +438,7 @@ let renderer = Renderer::builder()
         new_context.insert("author", author);
         new_context.insert("content", renderer.evaluate_multiple(expr, &new_context)?);
 
-        Ok(renderer.render(&blogpost_template, &new_context).unwrap().into())
+        Ok(RenderValue::Safe(renderer.render(&blogpost_template, &new_context)?))
     }))
     .build();
 let expr = r#"(html (body (blogpost (@ (title faketitle) (author me)) (div "my content here"))))"#;
@@ -126,15 +448,110 @@ let context = RenderContext::builder()
     .build();
 let html = renderer.render(&template, &context).unwrap();
 
-assert_eq!(html, "<!doctype html5><html><body><div><h2>faketitle</h2><span>posted by me</span><div>my content here</div><br /><div><span>#zxc</span><span>#xcv</span><span>#cvb</span></div></div></body></html>")
+assert_eq!(html, "<!DOCTYPE html><html><body><div><h2>faketitle</h2><span>posted by me</span><div>my content here</div><br><div><span>#zxc</span><span>#xcv</span><span>#cvb</span></div></div></body></html>")
+```
+
+a function added with a name that is already registered overrides it, the one added last
+wins. `try_build` returns an error for these instead, so that a builtin is not shadowed by
+accident, unless it was overridden with `replace`. builtins are removed with `without`,
+and `RendererBuilder::empty` starts without any of them. functions shared between projects
+can be registered under a namespace, so that they are called as `ui:button`.
+```rust
+use sato::renderer::{Renderer, RendererBuilder, RenderValue};
+use sato::context::RenderContext;
+use sato::template::Template;
+
+let renderer = RendererBuilder::empty()
+    .namespaced_function("ui", "button", Box::new(|_attrs, expr, renderer, context| {
+        let label = renderer.evaluate_multiple(expr, context)?.into_text();
+        Ok(RenderValue::Safe(format!(r#"<button class="ui">{}</button>"#, label)))
+    }))
+    .build();
+let template = Template::from_str(r#"(div (ui:button "ok") (if "plain tag"))"#).unwrap();
+let html = renderer.render(&template, &RenderContext::default()).unwrap();
+assert_eq!(html, r#"<div><button class="ui">ok</button><if>plain tag</if></div>"#);
+
+assert!(Renderer::builder().function("get", Box::new(|_, _, _, _| Ok(RenderValue::Empty))).try_build().is_err());
+assert!(Renderer::builder().replace("get", Box::new(|_, _, _, _| Ok(RenderValue::Empty))).try_build().is_ok());
+```
+
+
+# plugins
+functions, templates and default variables that are shared between projects can be bundled
+into a `SatoPlugin` and added with `RendererBuilder::plugin`. the builtins are the
+`CorePlugin`, which `Renderer::builder` starts with and `RendererBuilder::empty` does not.
+```rust
+use sato::plugin::SatoPlugin;
+use sato::renderer::{NodeHandler, Renderer, RenderValue};
+use sato::context::RenderContext;
+use sato::template::Template;
+
+struct Ui;
+
+impl SatoPlugin for Ui {
+    fn name(&self) -> &str {
+        "ui"
+    }
+
+    fn functions(&self) -> Vec<(String, Box<NodeHandler>)> {
+        vec![("ui:button".into(), Box::new(|_attrs, expr, renderer, context| {
+            let label = renderer.evaluate_multiple(expr, context)?.into_text();
+            Ok(RenderValue::Safe(format!("<button>{}</button>", label)))
+        }))]
+    }
+
+    fn context(&self) -> RenderContext {
+        RenderContext::builder()
+            .insert("copyright", "nobody")
+            .build()
+    }
+
+    fn templates(&self) -> Vec<(String, Template)> {
+        vec![("ui/footer".into(), Template::from_str(r#"(footer "(c) " $copyright)"#).unwrap())]
+    }
+}
+
+let renderer = Renderer::builder()
+    .plugin(Ui)
+    .build();
+let template = Template::from_str(r#"(div (ui:button "ok") (include "ui/footer"))"#).unwrap();
+let html = renderer.render(&template, &RenderContext::default()).unwrap();
+assert_eq!(html, "<div><button>ok</button><footer>(c) nobody</footer></div>");
 ```
 
+plugins are installed in the order they are added. a function or template registered under a
+name that is already taken overrides it, which `try_build` reports as an error, and when
+plugins set the same default variable the one added last wins. the context passed to a render
+always takes precedence over the defaults.
+
 
 # builtin functions
 ## if
 `(if [condition] [true code block] [false code block])`
 if condition evaluates to true then execute the true block, if false then execute false block.
 
+## and/or/not
+`(and [item] ...)`
+
+`(or [item] ...)`
+
+`(not [item])`
+
+boolean logic, `and` and `or` stop evaluating as soon as the result is known. these and `if`
+treat false, `0`, null, empty strings and empty arrays and maps as false, and everything else as true.
+
+## cond
+`(cond ([condition] [code block]) ([condition] [code block]) ... (else [code block]))`
+
+executes the code block of the first condition that is true, or the `else` block if none are.
+
+## when/unless
+`(when [condition] [code block])`
+
+`(unless [condition] [code block])`
+
+executes the code block if the condition is true (`when`) or false (`unless`).
+
 ## get
 `(get [array] [index])`
 
@@ -147,6 +564,16 @@ gets an element from an array or map
 
 takes a single argument and returns true or false depending if the variable is set.
 
+## default
+`(default [variable] [fallback code block])`
+
+evaluates to the variable, or to the fallback if the variable is not set or is null.
+
+## null?/is-null
+`(null? [item])`
+
+true if the item is null. unlike `is-set`, a variable that has been set to null counts as set.
+
 ## switch/case
 `(switch [variable] (case [value] [code block]) (case [value] [code block]) ...)`
 
@@ -159,7 +586,7 @@ takes a single argument and returns true or false depending if the variable is s
 
 `(for (enumerate [index] [item]) in [array] [code block])`
 
-executes code block for each element in the iterable.
+executes code block for each element in the iterable. a range's step must be positive.
 
 ## eq/gt/lt/gte/lte/ne
 `(eq [item] [item])`
@@ -171,24 +598,95 @@ standard math operators
 
 `(+ [item] [item])`
 
+two integers give an integer, so `(/ 7 2)` is `3`. if either side is a float like `2.0` the
+result is a float. integer division by zero and results that do not fit in an i64 are errors.
+
+## format-number
+`(format-number [number] [decimal places?])`
+
+formats a number with a fixed number of decimal places from 0 to 100, `0` if not given.
+
+## extends/block/super
+`(extends [template name] (block [name] [code block]) ...)`
+
+`(block [name] [code block])`
+
+`(super)`
+
+renders the named template with its blocks replaced by the ones given, see template inheritance.
+
+## include
+`(include [template name])`
+
+renders another template from the renderer's loader in the current context.
+
+## defcomponent/children/import
+`(defcomponent [name] ([parameter] ...) [code block])`
+
+`(children)`
+
+`(import [template name])`
+
+defines a component, see components.
+
+## let/with
+`(let (([name] [value]) ([name] [value]) ...) [code block])`
+
+evaluates each value and binds it to its name for the code block, later values can use
+earlier names. `with` is the same as `let`.
+
+## document
+`(document [code block])`
+
+outputs the document prolog for the renderer's dialect (the doctype and/or xml declaration)
+followed by the code block. `html` does this implicitly.
+
+## raw
+`(raw [item] ...)`
+
+outputs its arguments without html escaping.
+
 */
 
 
 mod builtins;
+pub mod compiled;
 pub mod context;
+pub mod escape;
+pub mod loader;
+pub mod plugin;
 pub mod renderer;
+pub mod sandbox;
 pub mod template;
-
-pub use crate::renderer::{Renderer, RenderValue, Attribute, Attributes, RenderError};
-pub use crate::template::{Template, TemplateExprNode};
+#[cfg(feature = "serde")]
+pub mod ser;
+#[cfg(feature = "serde")]
+pub mod de;
+
+pub use crate::renderer::{Renderer, RendererBuilder, RenderValue, Attribute, Attributes, Dialect, RenderError, BuildError};
+pub use crate::template::{Template, TemplateExprNode, Span};
+pub use crate::compiled::CompiledTemplate;
+pub use crate::sandbox::Sandbox;
+pub use crate::plugin::{SatoPlugin, CorePlugin};
+pub use crate::loader::{TemplateLoader, FileSystemLoader};
 pub use crate::context::{RenderContext, ContextValue};
+#[cfg(feature = "serde")]
+pub use crate::ser::{to_context_value, SerializeError};
+#[cfg(feature = "serde")]
+pub use crate::de::ContextError;
 
 
 #[cfg(test)]
 mod tests {
     use crate::context::{RenderContext, ContextValue};
-    use crate::renderer::{Renderer, RenderValue};
-    use crate::template::{Template, TemplateExprNode};
+    use crate::renderer::{Renderer, RendererBuilder, RenderValue, RenderError, Dialect, BuildError, NodeHandler};
+    use crate::plugin::{SatoPlugin, CorePlugin};
+    use crate::template::{Template, TemplateExprNode, TemplateError, ParseExprError};
+    use crate::loader::{FileSystemLoader, TemplateLoader};
+    use crate::sandbox::Sandbox;
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
 
     #[test]
     fn test_no_builtins() {
@@ -209,7 +707,7 @@ mod tests {
         let template = Template::from_str(expr).unwrap();
         let html = renderer.render(&template, &RenderContext::default()).unwrap();
 
-        assert_eq!(html, "<!doctype html5><html><head><title>test title</title></head></html>")
+        assert_eq!(html, "<!DOCTYPE html><html><head><title>test title</title></head></html>")
     }
 
     #[test]
@@ -219,7 +717,7 @@ mod tests {
         let expr = r#"(html (head (@ (asdf qwer) (zxc asd))(title "test title")))"#;
         let template = Template::from_str(expr).unwrap();
         let html = renderer.render(&template, &RenderContext::default()).unwrap();
-        assert_eq!(html, r#"<!doctype html5><html><head asdf="qwer" zxc="asd"><title>test title</title></head></html>"#)
+        assert_eq!(html, r#"<!DOCTYPE html><html><head asdf="qwer" zxc="asd"><title>test title</title></head></html>"#)
     }
 
     #[test]
@@ -229,7 +727,7 @@ mod tests {
         let expr = r#"(html (body (@ (asdf qwer))))"#;
         let template = Template::from_str(expr).unwrap();
         let html = renderer.render(&template, &RenderContext::default()).unwrap();
-        assert_eq!(html, r#"<!doctype html5><html><body asdf="qwer" /></html>"#)
+        assert_eq!(html, r#"<!DOCTYPE html><html><body asdf="qwer"></body></html>"#)
     }
 
     #[test]
@@ -242,7 +740,7 @@ mod tests {
             .insert("title", "some sort of title")
             .build();
         let html = renderer.render(&template, &context).unwrap();
-        assert_eq!(html, r#"<!doctype html5><html><head><title>some sort of title</title></head></html>"#)
+        assert_eq!(html, r#"<!DOCTYPE html><html><head><title>some sort of title</title></head></html>"#)
     }
 
     #[test]
@@ -256,7 +754,22 @@ mod tests {
             .insert("vec", vec!["this", "that", "$asdf"])
             .build();
         let html = renderer.render(&template, &context).unwrap();
-        assert_eq!(html, r#"<!doctype html5><html><div>thisthatqwer</div></html>"#)
+        // strings in the context are data, never variables
+        assert_eq!(html, r#"<!DOCTYPE html><html><div>thisthat$asdf</div></html>"#)
+    }
+
+    #[test]
+    fn test_vec_strings_are_not_variables_in_strict_mode() {
+        let renderer = Renderer::builder()
+            .strict(true)
+            .build();
+        let template = Template::from_str(r#"(ul (for t in $tags (li $t)))"#).unwrap();
+        let context = RenderContext::builder()
+            .insert("tags", vec!["$5 off", "$secret"])
+            .insert("secret", "hunter2")
+            .build();
+        let html = renderer.render(&template, &context).unwrap();
+        assert_eq!(html, r#"<ul><li>$5 off</li><li>$secret</li></ul>"#)
     }
 
     #[test]
@@ -269,7 +782,7 @@ mod tests {
             .insert("title", "some sort of title")
             .build();
         let html = renderer.render(&template, &context).unwrap();
-        assert_eq!(html, r#"<!doctype html5><html><head><title>some sort of title</title></head></html>"#)
+        assert_eq!(html, r#"<!DOCTYPE html><html><head><title>some sort of title</title></head></html>"#)
     }
 
     #[test]
@@ -282,7 +795,7 @@ mod tests {
             .insert("title2", "some sort of title")
             .build();
         let html = renderer.render(&template, &context).unwrap();
-        assert_eq!(html, r#"<!doctype html5><html><head><title>not set</title></head></html>"#)
+        assert_eq!(html, r#"<!DOCTYPE html><html><head><title>not set</title></head></html>"#)
     }
 
     #[test]
@@ -295,7 +808,7 @@ mod tests {
             .insert("asdf", vec!["qaz", "wsx", "edc"])
             .build();
         let html = renderer.render(&template, &context).unwrap();
-        assert_eq!(html, r#"<!doctype html5><html><body><div>iter qaz</div><div>iter wsx</div><div>iter edc</div></body></html>"#)
+        assert_eq!(html, r#"<!DOCTYPE html><html><body><div>iter qaz</div><div>iter wsx</div><div>iter edc</div></body></html>"#)
     }
 
     #[test]
@@ -305,7 +818,7 @@ mod tests {
         let expr = r#"(html (body (for i in (range 0 3) (div "iter " $i))))"#;
         let template = Template::from_str(expr).unwrap();
         let html = renderer.render(&template, &RenderContext::default()).unwrap();
-        assert_eq!(html, r#"<!doctype html5><html><body><div>iter 0</div><div>iter 1</div><div>iter 2</div></body></html>"#)
+        assert_eq!(html, r#"<!DOCTYPE html><html><body><div>iter 0</div><div>iter 1</div><div>iter 2</div></body></html>"#)
     }
 
     #[test]
@@ -318,7 +831,7 @@ mod tests {
             .insert("asdf", vec!["qaz", "wsx", "edc"])
             .build();
         let html = renderer.render(&template, &context).unwrap();
-        assert_eq!(html, r#"<!doctype html5><html><body><div>0: iter qaz</div><div>1: iter wsx</div><div>2: iter edc</div></body></html>"#)
+        assert_eq!(html, r#"<!DOCTYPE html><html><body><div>0: iter qaz</div><div>1: iter wsx</div><div>2: iter edc</div></body></html>"#)
     }
 
     #[test]
@@ -335,7 +848,7 @@ mod tests {
             .insert("asdf", internal_obj)
             .build();
         let html = renderer.render(&template, &context).unwrap();
-        assert_eq!(html, r#"<!doctype html5><html><body><div>key as, value df</div><div>key qw, value er</div></body></html>"#)
+        assert_eq!(html, r#"<!DOCTYPE html><html><body><div>key as, value df</div><div>key qw, value er</div></body></html>"#)
     }
 
     #[test]
@@ -352,7 +865,7 @@ mod tests {
             .insert("asdf", internal_obj)
             .build();
         let html = renderer.render(&template, &context).unwrap();
-        assert_eq!(html, r#"<!doctype html5><html><body>df</body></html>"#)
+        assert_eq!(html, r#"<!DOCTYPE html><html><body>df</body></html>"#)
     }
 
     #[test]
@@ -379,7 +892,7 @@ mod tests {
             .insert("nested_object", nested0_obj)
             .build();
         let html = renderer.render(&template, &context).unwrap();
-        assert_eq!(html, r#"<!doctype html5><html><body>look at this nested thing</body></html>"#)
+        assert_eq!(html, r#"<!DOCTYPE html><html><body>look at this nested thing</body></html>"#)
     }
 
     #[test]
@@ -392,7 +905,7 @@ mod tests {
             .insert("qwer", "zxcv")
             .build();
         let html = renderer.render(&template, &context).unwrap();
-        assert_eq!(html, r#"<!doctype html5><html><head asdf="zxcv" zxc="asd"><title>test title</title></head></html>"#)
+        assert_eq!(html, r#"<!DOCTYPE html><html><head asdf="zxcv" zxc="asd"><title>test title</title></head></html>"#)
     }
 
     #[test]
@@ -415,7 +928,7 @@ mod tests {
             .insert("as", vec![obj1, obj2])
             .build();
         let html = renderer.render(&template, &context).unwrap();
-        assert_eq!(html, r#"<!doctype html5><html><div class="cv">hj</div><div class="df">nm</div></html>"#)
+        assert_eq!(html, r#"<!DOCTYPE html><html><div class="cv">hj</div><div class="df">nm</div></html>"#)
     }
 
     #[test]
@@ -428,7 +941,7 @@ mod tests {
             .insert("blah", "zxcv")
             .build();
         let html = renderer.render(&template, &context).unwrap();
-        assert_eq!(html, r#"<!doctype html5><html><div><div>whatelse</div></div></html>"#)
+        assert_eq!(html, r#"<!DOCTYPE html><html><div><div>whatelse</div></div></html>"#)
     }
 
     #[test]
@@ -441,7 +954,7 @@ mod tests {
         let expr = r#"(html (div (blah something or other)))"#;
         let template = Template::from_str(expr).unwrap();
         let html = renderer.render(&template, &RenderContext::default()).unwrap();
-        assert_eq!(html, r#"<!doctype html5><html><div>hello there</div></html>"#)
+        assert_eq!(html, r#"<!DOCTYPE html><html><div>hello there</div></html>"#)
     }
 
     #[test]
@@ -456,7 +969,7 @@ mod tests {
         let expr = r#"(html (div (blarg something or other)))"#;
         let template = Template::from_str(expr).unwrap();
         let html = renderer.render(&template, &RenderContext::default()).unwrap();
-        assert_eq!(html, r#"<!doctype html5><html><div>hello there</div></html>"#)
+        assert_eq!(html, r#"<!DOCTYPE html><html><div>hello there</div></html>"#)
     }
 
     #[test]
@@ -469,7 +982,7 @@ mod tests {
                     output.push("[".into());
                     output.push(attr.0.clone());
                     output.push(" = ".into());
-                    output.push(attr.1.clone());
+                    output.push(attr.1.clone().unwrap_or_default());
                     output.push("] ".into());
                 }
 
@@ -479,24 +992,25 @@ mod tests {
         let expr = r#"(html (div (blah (@ (this is) (the attr)) something or other)))"#;
         let template = Template::from_str(expr).unwrap();
         let html = renderer.render(&template, &RenderContext::default()).unwrap();
-        assert_eq!(html, r#"<!doctype html5><html><div>[this = is] [the = attr] </div></html>"#)
+        assert_eq!(html, r#"<!DOCTYPE html><html><div>[this = is] [the = attr] </div></html>"#)
     }
 
     #[test]
     fn test_more_html_in_closure() {
         let renderer = Renderer::builder()
             .function("blah", Box::new(|_, expr, renderer, context| {
-                let mut output: Vec<RenderValue> = Vec::new();
-                output.push("<blah>".into());
-                output.push(renderer.evaluate_multiple(expr, context)?.into());
-                output.push("</blah>".into());
+                let output: Vec<RenderValue> = vec![
+                    RenderValue::Safe("<blah>".into()),
+                    renderer.evaluate_multiple(expr, context)?,
+                    RenderValue::Safe("</blah>".into()),
+                ];
                 Ok(output.into())
             }))
             .build();
         let expr = r#"(html (div (blah (span hello))))"#;
         let template = Template::from_str(expr).unwrap();
         let html = renderer.render(&template, &RenderContext::default()).unwrap();
-        assert_eq!(html, r#"<!doctype html5><html><div><blah><span>hello</span></blah></div></html>"#)
+        assert_eq!(html, r#"<!DOCTYPE html><html><div><blah><span>hello</span></blah></div></html>"#)
     }
 
     #[test]
@@ -506,18 +1020,18 @@ mod tests {
 
         let renderer = Renderer::builder()
             .function("blah", Box::new(move |_, _, renderer, _| {
-                let mut output: Vec<String> = Vec::new();
-                output.push("<blah>".into());
+                let mut output: Vec<RenderValue> = Vec::new();
+                output.push(RenderValue::Safe("<blah>".into()));
                 let suboutput = renderer.render(&subtemplate, &RenderContext::default())?;
-                output.push(suboutput);
-                output.push("</blah>".into());
+                output.push(RenderValue::Safe(suboutput));
+                output.push(RenderValue::Safe("</blah>".into()));
                 Ok(output.into())
             }))
             .build();
         let expr = r#"(html (div (blah (span hello))))"#;
         let template = Template::from_str(expr).unwrap();
         let html = renderer.render(&template, &RenderContext::default()).unwrap();
-        assert_eq!(html, r#"<!doctype html5><html><div><blah><sub>str</sub></blah></div></html>"#)
+        assert_eq!(html, r#"<!DOCTYPE html><html><div><blah><sub>str</sub></blah></div></html>"#)
     }
 
     #[test]
@@ -529,10 +1043,11 @@ mod tests {
                     _ => panic!("not a str")
                 }.clone();
 
-                let mut output: Vec<String> = Vec::new();
-                output.push("<blah>".into());
-                output.push(s);
-                output.push("</blah>".into());
+                let output: Vec<RenderValue> = vec![
+                    RenderValue::Safe("<blah>".into()),
+                    s.into(),
+                    RenderValue::Safe("</blah>".into()),
+                ];
                 Ok(output.into())
             }))
             .build();
@@ -542,7 +1057,7 @@ mod tests {
             .insert("blah", "zxcv")
             .build();
         let html = renderer.render(&template, &context).unwrap();
-        assert_eq!(html, r#"<!doctype html5><html><div><blah>zxcv</blah></div></html>"#)
+        assert_eq!(html, r#"<!DOCTYPE html><html><div><blah>zxcv</blah></div></html>"#)
     }
 
     #[test]
@@ -553,19 +1068,19 @@ mod tests {
         let renderer = Renderer::builder()
             .function("blah", Box::new(move |attr, expr, renderer, context| {
                 let mut output: Vec<RenderValue> = Vec::new();
-                output.push("<blah>".into());
+                output.push(RenderValue::Safe("<blah>".into()));
 
                 let mut subcontext = RenderContext::default();
                 subcontext.insert("content", attr.get("something").unwrap().clone());
                 let suboutput = renderer.render(&subtemplate, &subcontext)?;
-                output.push(suboutput.into());
-                output.push(renderer.evaluate_multiple(expr, context)?.into());
+                output.push(RenderValue::Safe(suboutput));
+                output.push(renderer.evaluate_multiple(expr, context)?);
                 output.push(match context.get("blah").unwrap() {
                     ContextValue::String(s) => s,
                     _ => panic!("not a str")
                 }.clone().into());
 
-                output.push("</blah>".into());
+                output.push(RenderValue::Safe("</blah>".into()));
                 Ok(output.into())
             }))
             .build();
@@ -575,7 +1090,7 @@ mod tests {
             .insert("blah", "zxcv")
             .build();
         let html = renderer.render(&template, &context).unwrap();
-        assert_eq!(html, r#"<!doctype html5><html><div><blah><sub>extra</sub><span>hello</span>zxcv</blah></div></html>"#)
+        assert_eq!(html, r#"<!DOCTYPE html><html><div><blah><sub>extra</sub><span>hello</span>zxcv</blah></div></html>"#)
     }
 
     #[test]
@@ -587,7 +1102,7 @@ mod tests {
         let context = RenderContext::builder()
             .build();
         let html = renderer.render(&template, &context).unwrap();
-        assert_eq!(html, r#"<!doctype html5><html><body><div>0</div><div>2</div><div>4</div></body></html>"#)
+        assert_eq!(html, r#"<!DOCTYPE html><html><body><div>0</div><div>2</div><div>4</div></body></html>"#)
     }
 
     #[test]
@@ -601,7 +1116,7 @@ mod tests {
             .insert("b", 7)
             .build();
         let html = renderer.render(&template, &context).unwrap();
-        assert_eq!(html, r#"<!doctype html5><html><body><div>iter 4</div><div>iter 5</div><div>iter 6</div></body></html>"#)
+        assert_eq!(html, r#"<!DOCTYPE html><html><body><div>iter 4</div><div>iter 5</div><div>iter 6</div></body></html>"#)
     }
 
     #[test]
@@ -616,7 +1131,7 @@ mod tests {
             .insert("c", 12)
             .build();
         let html = renderer.render(&template, &context).unwrap();
-        assert_eq!(html, r#"<!doctype html5><html><body>23</body></html>"#)
+        assert_eq!(html, r#"<!DOCTYPE html><html><body>23</body></html>"#)
     }
 
     #[test]
@@ -630,7 +1145,7 @@ mod tests {
             .insert("b", 7)
             .build();
         let html = renderer.render(&template, &context).unwrap();
-        assert_eq!(html, r#"<!doctype html5><html><body blah="5" asdf="28" /></html>"#)
+        assert_eq!(html, r#"<!DOCTYPE html><html><body blah="5" asdf="28"></body></html>"#)
     }
 
     #[test]
@@ -644,7 +1159,7 @@ mod tests {
             .insert("b", 7)
             .build();
         let html = renderer.render(&template, &context).unwrap();
-        assert_eq!(html, r#"<!doctype html5><html><body><div>iter 3</div><div>iter 4</div><div>iter 5</div><div>iter 6</div><div>iter 7</div></body></html>"#)
+        assert_eq!(html, r#"<!DOCTYPE html><html><body><div>iter 3</div><div>iter 4</div><div>iter 5</div><div>iter 6</div><div>iter 7</div></body></html>"#)
     }
 
     #[test]
@@ -657,7 +1172,7 @@ mod tests {
             .insert("a", vec!["asd", "qwe", "zxc"])
             .build();
         let html = renderer.render(&template, &context).unwrap();
-        assert_eq!(html, r#"<!doctype html5><html><body>asdzxc</body></html>"#)
+        assert_eq!(html, r#"<!DOCTYPE html><html><body>asdzxc</body></html>"#)
     }
 
     #[test]
@@ -675,7 +1190,7 @@ mod tests {
             .insert("asdf", internal_obj)
             .build();
         let html = renderer.render(&template, &context).unwrap();
-        assert_eq!(html, r#"<!doctype html5><html><body>dfcv</body></html>"#)
+        assert_eq!(html, r#"<!DOCTYPE html><html><body>dfcv</body></html>"#)
     }
 
     #[test]
@@ -691,7 +1206,7 @@ mod tests {
             .insert("a", v)
             .build();
         let html = renderer.render(&template, &context).unwrap();
-        assert_eq!(html, r#"<!doctype html5><html><body>blah</body></html>"#)
+        assert_eq!(html, r#"<!DOCTYPE html><html><body>blah</body></html>"#)
     }
 
     #[test]
@@ -706,7 +1221,7 @@ mod tests {
             .insert("a", v)
             .build();
         let html = renderer.render(&template, &context).unwrap();
-        assert_eq!(html, r#"<!doctype html5><html><body>blah123thistrue</body></html>"#)
+        assert_eq!(html, r#"<!DOCTYPE html><html><body>blah123thistrue</body></html>"#)
     }
 
     #[test]
@@ -719,7 +1234,7 @@ mod tests {
             .insert("a", "blah")
             .build();
         let html = renderer.render(&template, &context).unwrap();
-        assert_eq!(html, r#"<!doctype html5><html><body asdf="thisthat" some="thingblah" /></html>"#)
+        assert_eq!(html, r#"<!DOCTYPE html><html><body asdf="thisthat" some="thingblah"></body></html>"#)
     }
 
     #[test]
@@ -733,7 +1248,7 @@ mod tests {
             .insert("b", "this")
             .build();
         let html = renderer.render(&template, &context).unwrap();
-        assert_eq!(html, r#"<!doctype html5><html><body this="asdfblah" /></html>"#)
+        assert_eq!(html, r#"<!DOCTYPE html><html><body this="asdfblah"></body></html>"#)
     }
 
     #[test]
@@ -748,7 +1263,7 @@ mod tests {
             .insert("body", sub_template)
             .build();
         let html = renderer.render(&main_template, &context).unwrap();
-        assert_eq!(html, r#"<!doctype html5><html><body><span>hello</span></body></html>"#)
+        assert_eq!(html, r#"<!DOCTYPE html><html><body><span>hello</span></body></html>"#)
     }
 
     #[test]
@@ -778,7 +1293,7 @@ mod tests {
         let main_template = Template::from_str(main_expr).unwrap();
         let html = renderer.render(&main_template, &context).unwrap();
 
-        assert_eq!(html, r#"<!doctype html5><html><div>1[23]</div><div>2[34]</div></html>"#)
+        assert_eq!(html, r#"<!DOCTYPE html><html><div>1[23]</div><div>2[34]</div></html>"#)
     }
 
     #[test]
@@ -795,6 +1310,1764 @@ mod tests {
             .insert("as", subcontext)
             .build();
         let html = renderer.render(&template, &context).unwrap();
-        assert_eq!(html, r#"<!doctype html5><html>2</html>"#)
+        assert_eq!(html, r#"<!DOCTYPE html><html>2</html>"#)
+    }
+
+    #[test]
+    fn test_variable_is_escaped() {
+        let renderer = Renderer::builder()
+            .build();
+        let expr = r#"(html (body $a "<b>&</b>"))"#;
+        let template = Template::from_str(expr).unwrap();
+        let context = RenderContext::builder()
+            .insert("a", "<script>alert(1)</script>")
+            .build();
+        let html = renderer.render(&template, &context).unwrap();
+        assert_eq!(html, r#"<!DOCTYPE html><html><body>&lt;script&gt;alert(1)&lt;/script&gt;&lt;b&gt;&amp;&lt;/b&gt;</body></html>"#)
+    }
+
+    #[test]
+    fn test_attribute_is_escaped() {
+        let renderer = Renderer::builder()
+            .build();
+        let expr = r#"(html (body (@ (title $a))))"#;
+        let template = Template::from_str(expr).unwrap();
+        let context = RenderContext::builder()
+            .insert("a", r#""><script>'&"#)
+            .build();
+        let html = renderer.render(&template, &context).unwrap();
+        assert_eq!(html, r#"<!DOCTYPE html><html><body title="&quot;&gt;&lt;script&gt;&#39;&amp;"></body></html>"#)
+    }
+
+    #[test]
+    fn test_invalid_attribute_name() {
+        let renderer = Renderer::builder()
+            .build();
+        let template = Template::from_str(r#"(div (@ ($nm "v")) hi)"#).unwrap();
+        for name in ["x onmouseover=alert(1) y", "a\"b", "a>", "a/b", "a=b", "a'b", ""] {
+            let context = RenderContext::builder()
+                .insert("nm", name)
+                .build();
+            let err = renderer.render(&template, &context).unwrap_err();
+            assert!(matches!(err.root(), RenderError::InvalidAttributeName(n) if n == name), "{}", name);
+            let err = renderer.render_compiled(&renderer.compile(&template), &context).unwrap_err();
+            assert!(matches!(err.root(), RenderError::InvalidAttributeName(n) if n == name), "{}", name);
+        }
+        let context = RenderContext::builder()
+            .insert("nm", "data-x:y.z")
+            .build();
+        assert_eq!(renderer.render(&template, &context).unwrap(), r#"<div data-x:y.z="v">hi</div>"#);
+    }
+
+    #[test]
+    fn test_raw() {
+        let renderer = Renderer::builder()
+            .build();
+        let expr = r#"(html (body (raw $a "&nbsp;")))"#;
+        let template = Template::from_str(expr).unwrap();
+        let context = RenderContext::builder()
+            .insert("a", "<b>bold</b>")
+            .build();
+        let html = renderer.render(&template, &context).unwrap();
+        assert_eq!(html, r#"<!DOCTYPE html><html><body><b>bold</b>&nbsp;</body></html>"#)
+    }
+
+    #[test]
+    fn test_safe_context_value() {
+        let renderer = Renderer::builder()
+            .build();
+        let expr = r#"(html (body $a $b))"#;
+        let template = Template::from_str(expr).unwrap();
+        let context = RenderContext::builder()
+            .insert("a", ContextValue::Safe("<hr>".into()))
+            .insert("b", "<hr>")
+            .build();
+        let html = renderer.render(&template, &context).unwrap();
+        assert_eq!(html, r#"<!DOCTYPE html><html><body><hr>&lt;hr&gt;</body></html>"#)
+    }
+
+    #[test]
+    fn test_boolean_attributes() {
+        let renderer = Renderer::builder()
+            .build();
+        let expr = r#"(html (input (@ (type checkbox) (disabled) (checked $a) (readonly $b) (hidden $c.d))))"#;
+        let template = Template::from_str(expr).unwrap();
+        let context = RenderContext::builder()
+            .insert("a", true)
+            .insert("b", false)
+            .insert("c", RenderContext::default())
+            .build();
+        let html = renderer.render(&template, &context).unwrap();
+        assert_eq!(html, r#"<!DOCTYPE html><html><input type="checkbox" disabled checked></html>"#)
+    }
+
+    #[test]
+    fn test_boolean_attribute_unset() {
+        let renderer = Renderer::builder()
+            .build();
+        let template = Template::from_str(r#"(input (@ (type checkbox) (checked $is_checked) (title "x" $is_checked)))"#).unwrap();
+        let html = renderer.render(&template, &RenderContext::default()).unwrap();
+        assert_eq!(html, r#"<input type="checkbox" title="x$is_checked">"#);
+        let html = renderer.render_compiled(&renderer.compile(&template), &RenderContext::default()).unwrap();
+        assert_eq!(html, r#"<input type="checkbox" title="x$is_checked">"#);
+    }
+
+    #[test]
+    fn test_boolean_attribute_literal_false() {
+        let renderer = Renderer::builder()
+            .build();
+        let template = Template::from_str(r#"(div (@ (draggable false) (spellcheck "false") (hidden $b)))"#).unwrap();
+        let context = RenderContext::builder()
+            .insert("b", false)
+            .build();
+        let expected = r#"<div draggable="false" spellcheck="false"></div>"#;
+        assert_eq!(renderer.render(&template, &context).unwrap(), expected);
+        assert_eq!(renderer.render_compiled(&renderer.compile(&template), &context).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_boolean_attributes_in_closure() {
+        let renderer = Renderer::builder()
+            .function("blah", Box::new(|attrs, _, _, _| {
+                Ok(format!("{} {} {:?}", attrs.has("disabled"), attrs.has("checked"), attrs.get("disabled")).into())
+            }))
+            .build();
+        let expr = r#"(html (blah (@ (disabled) (checked $a))))"#;
+        let template = Template::from_str(expr).unwrap();
+        let context = RenderContext::builder()
+            .insert("a", false)
+            .build();
+        let html = renderer.render(&template, &context).unwrap();
+        assert_eq!(html, r#"<!DOCTYPE html><html>true false None</html>"#)
+    }
+
+    #[test]
+    fn test_void_and_normal_elements() {
+        let renderer = Renderer::builder()
+            .build();
+        let expr = r#"(html (body (div) (br) (img (@ (src $a))) (p)))"#;
+        let template = Template::from_str(expr).unwrap();
+        let context = RenderContext::builder()
+            .insert("a", "a.png")
+            .build();
+        let html = renderer.render(&template, &context).unwrap();
+        assert_eq!(html, r#"<!DOCTYPE html><html><body><div></div><br><img src="a.png"><p></p></body></html>"#)
+    }
+
+    #[test]
+    fn test_void_element_with_children() {
+        let renderer = Renderer::builder()
+            .build();
+        let expr = r#"(html (body (br "text")))"#;
+        let template = Template::from_str(expr).unwrap();
+        let err = renderer.render(&template, &RenderContext::default()).unwrap_err();
+        assert!(matches!(err.root(), RenderError::VoidElement(tag) if tag == "br"))
+    }
+
+    #[test]
+    fn test_raw_text_element() {
+        let renderer = Renderer::builder()
+            .build();
+        let expr = r#"(html (head (script "if (a < b && c > d) {}" $a)))"#;
+        let template = Template::from_str(expr).unwrap();
+        let context = RenderContext::builder()
+            .insert("a", "var x = '<b>';")
+            .build();
+        let html = renderer.render(&template, &context).unwrap();
+        assert_eq!(html, r#"<!DOCTYPE html><html><head><script>if (a < b && c > d) {}var x = '<b>';</script></head></html>"#)
+    }
+
+    #[test]
+    fn test_raw_text_element_injection() {
+        let renderer = Renderer::builder()
+            .build();
+        let expr = r#"(html (head (script $a)))"#;
+        let template = Template::from_str(expr).unwrap();
+        let context = RenderContext::builder()
+            .insert("a", "</SCRIPT><script>alert(1)")
+            .build();
+        let err = renderer.render(&template, &context).unwrap_err();
+        assert!(matches!(err.root(), RenderError::RawText(tag) if tag == "script"))
+    }
+
+    #[test]
+    fn test_xhtml_dialect() {
+        let renderer = Renderer::builder()
+            .dialect(Dialect::Xhtml)
+            .build();
+        let expr = r#"(html (body (div) (br) (input (@ (disabled)))))"#;
+        let template = Template::from_str(expr).unwrap();
+        let html = renderer.render(&template, &RenderContext::default()).unwrap();
+        assert_eq!(html, r#"<?xml version="1.0" encoding="UTF-8"?><!DOCTYPE html><html xmlns="http://www.w3.org/1999/xhtml"><body><div></div><br /><input disabled="disabled" /></body></html>"#)
+    }
+
+    #[test]
+    fn test_xml_dialect() {
+        let renderer = Renderer::builder()
+            .dialect(Dialect::Xml)
+            .build();
+        let expr = r#"(document (rss (@ (version "2.0")) (channel (title $title) (link) (br "not void here") (script "<escaped>"))))"#;
+        let template = Template::from_str(expr).unwrap();
+        let context = RenderContext::builder()
+            .insert("title", "a & b")
+            .build();
+        let xml = renderer.render(&template, &context).unwrap();
+        assert_eq!(xml, r#"<?xml version="1.0" encoding="UTF-8"?><rss version="2.0"><channel><title>a &amp; b</title><link /><br>not void here</br><script>&lt;escaped&gt;</script></channel></rss>"#)
+    }
+
+    #[test]
+    fn test_extends() {
+        let base = Template::from_str(r#"(html (head (title (block title "base title"))) (body (block content (p "base content")) (block footer "footer")))"#).unwrap();
+        let renderer = Renderer::builder()
+            .template("base.sato", base)
+            .build();
+        let expr = r#"(extends "base.sato" (block title $title) (block content (super) (p "child content")))"#;
+        let template = Template::from_str(expr).unwrap();
+        let context = RenderContext::builder()
+            .insert("title", "child title")
+            .build();
+        let html = renderer.render(&template, &context).unwrap();
+        assert_eq!(html, r#"<!DOCTYPE html><html><head><title>child title</title></head><body><p>base content</p><p>child content</p>footer</body></html>"#)
+    }
+
+    #[test]
+    fn test_multilevel_extends() {
+        let base = Template::from_str(r#"(html (body (block nav "base nav") (block content "base content")))"#).unwrap();
+        let layout = Template::from_str(r#"(extends "base.sato" (block content (div (@ (class layout)) (block inner "layout inner"))))"#).unwrap();
+        let renderer = Renderer::builder()
+            .template("base.sato", base)
+            .template("layout.sato", layout)
+            .build();
+        let expr = r#"(extends "layout.sato" (block inner "[" (super) "]") (block nav "child nav " (super)))"#;
+        let template = Template::from_str(expr).unwrap();
+        let html = renderer.render(&template, &RenderContext::default()).unwrap();
+        assert_eq!(html, r#"<!DOCTYPE html><html><body>child nav base nav<div class="layout">[layout inner]</div></body></html>"#)
+    }
+
+    #[test]
+    fn test_extends_errors() {
+        let a = Template::from_str(r#"(extends "b.sato")"#).unwrap();
+        let b = Template::from_str(r#"(extends "a.sato")"#).unwrap();
+        let renderer = Renderer::builder()
+            .template("a.sato", a.clone())
+            .template("b.sato", b)
+            .build();
+        let err = renderer.render(&a, &RenderContext::default()).unwrap_err();
+        assert!(matches!(err.root(), RenderError::Extends(..)));
+
+        let template = Template::from_str(r#"(extends "missing.sato")"#).unwrap();
+        let err = renderer.render(&template, &RenderContext::default()).unwrap_err();
+        assert!(matches!(err.root(), RenderError::Load(name, _) if name == "missing.sato"));
+    }
+
+    #[test]
+    fn test_include() {
+        let mut templates = HashMap::new();
+        templates.insert("nav.sato".to_string(), Template::from_str(r#"(nav (for i in $links (a $i)))"#).unwrap());
+        templates.insert("partials/footer.sato".to_string(), Template::from_str(r#"(footer (include "nav.sato"))"#).unwrap());
+        let renderer = Renderer::builder()
+            .loader(Box::new(templates))
+            .build();
+        let expr = r#"(html (body (include "nav.sato") (include "partials/footer.sato")))"#;
+        let template = Template::from_str(expr).unwrap();
+        let context = RenderContext::builder()
+            .insert("links", vec!["a", "b"])
+            .build();
+        let html = renderer.render(&template, &context).unwrap();
+        assert_eq!(html, r#"<!DOCTYPE html><html><body><nav><a>a</a><a>b</a></nav><footer><nav><a>a</a><a>b</a></nav></footer></body></html>"#)
+    }
+
+    #[test]
+    fn test_include_cycle() {
+        let mut templates = HashMap::new();
+        templates.insert("a.sato".to_string(), Template::from_str(r#"(div (include "b.sato"))"#).unwrap());
+        templates.insert("b.sato".to_string(), Template::from_str(r#"(div (include "c.sato"))"#).unwrap());
+        templates.insert("c.sato".to_string(), Template::from_str(r#"(div (include "a.sato"))"#).unwrap());
+        let renderer = Renderer::builder()
+            .loader(Box::new(templates))
+            .build();
+        let template = Template::from_str(r#"(include "a.sato")"#).unwrap();
+        let err = renderer.render(&template, &RenderContext::default()).unwrap_err();
+        assert_eq!(err.root().to_string(), "include cycle: a.sato -> b.sato -> c.sato -> a.sato")
+    }
+
+    #[test]
+    fn test_include_chain_is_not_in_context() {
+        let mut templates = HashMap::new();
+        templates.insert("a.sato".to_string(), Template::from_str(r#"(div (include "a.sato"))"#).unwrap());
+        templates.insert("b.sato".to_string(), Template::from_str(r#"(div $__include)"#).unwrap());
+        let renderer = Renderer::builder()
+            .loader(Box::new(templates))
+            .strict(true)
+            .build();
+        let template = Template::from_str(r#"(include "b.sato")"#).unwrap();
+        let err = renderer.render(&template, &RenderContext::default()).unwrap_err();
+        assert!(matches!(err.root(), RenderError::UndefinedVariable(name) if name == "__include"));
+
+        // a render that failed inside an include leaves nothing behind for the next one
+        let template = Template::from_str(r#"(include "a.sato")"#).unwrap();
+        for _ in 0..2 {
+            let err = renderer.render(&template, &RenderContext::default()).unwrap_err();
+            assert!(matches!(err.root(), RenderError::IncludeCycle(chain) if chain.len() == 2));
+        }
+    }
+
+    #[test]
+    fn test_filesystem_loader() {
+        let root = std::env::temp_dir().join(format!("sato-loader-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("partials")).unwrap();
+        std::fs::write(root.join("partials").join("nav.sato"), "(nav home)").unwrap();
+
+        let renderer = Renderer::builder()
+            .loader(Box::new(FileSystemLoader::new(&root)))
+            .build();
+        let template = Template::from_str(r#"(html (include "partials/nav.sato"))"#).unwrap();
+        let html = renderer.render(&template, &RenderContext::default()).unwrap();
+        assert_eq!(html, r#"<!DOCTYPE html><html><nav>home</nav></html>"#);
+
+        let template = Template::from_str(r#"(include "../partials/nav.sato")"#).unwrap();
+        let err = renderer.render(&template, &RenderContext::default()).unwrap_err();
+        assert!(matches!(err.root(), RenderError::Load(_, TemplateError::InvalidPath(_))));
+
+        let template = Template::from_str(r#"(include "missing.sato")"#).unwrap();
+        let err = renderer.render(&template, &RenderContext::default()).unwrap_err();
+        assert!(matches!(err.root(), RenderError::Load(_, TemplateError::NotFound(_))));
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_defcomponent() {
+        let renderer = Renderer::builder()
+            .build();
+        let expr = r#"(html
+                       (defcomponent card (title subtitle)
+                         (div (@ (class card))
+                           (h2 $title)
+                           (if (is-set $subtitle) (h3 $subtitle))
+                           (children)))
+                       (body
+                         (card (@ (title "first") (subtitle $sub)) (p "body " $sub))
+                         (card (@ (title "second")))))"#;
+        let template = Template::from_str(expr).unwrap();
+        let context = RenderContext::builder()
+            .insert("sub", "one")
+            .build();
+        let html = renderer.render(&template, &context).unwrap();
+        assert_eq!(html, r#"<!DOCTYPE html><html><body><div class="card"><h2>first</h2><h3>one</h3><p>body one</p></div><div class="card"><h2>second</h2></div></body></html>"#)
+    }
+
+    #[test]
+    fn test_defcomponent_params_are_scoped() {
+        let renderer = Renderer::builder()
+            .build();
+        let expr = r#"(div
+                       (defcomponent item (value) (li (if (is-set $value) $value "none")))
+                       (defcomponent list () (ul (children)))
+                       (list (item (@ (value $a))) (item))
+                       $value)"#;
+        let template = Template::from_str(expr).unwrap();
+        let context = RenderContext::builder()
+            .insert("a", vec![1, 2])
+            .insert("value", "outer")
+            .build();
+        let html = renderer.render(&template, &context).unwrap();
+        assert_eq!(html, r#"<div><ul><li>12</li><li>none</li></ul>outer</div>"#)
+    }
+
+    #[test]
+    fn test_component_state_is_not_in_context() {
+        let renderer = Renderer::builder()
+            .strict(true)
+            .function("keys", Box::new(|_, _, _, context| {
+                let mut keys = context.0.keys().cloned().collect::<Vec<_>>();
+                keys.sort();
+                Ok(RenderValue::String(keys.join(",")))
+            }))
+            .build();
+        let context = RenderContext::builder()
+            .insert("a", 1)
+            .build();
+        let template = Template::from_str("(div (defcomponent card (title) (p (keys) (children))) (card (@ (title x)) (b (keys))))").unwrap();
+        assert_eq!(renderer.render(&template, &context).unwrap(), "<div><p>a,title<b>a</b></p></div>");
+        for var in ["__children", "__components"] {
+            let template = Template::from_str(&format!("(div (defcomponent card () (p ${})) (card x))", var)).unwrap();
+            let err = renderer.render(&template, &context).unwrap_err();
+            assert!(matches!(err.root(), RenderError::UndefinedVariable(name) if name == var), "{}", var);
+        }
+    }
+
+    #[test]
+    fn test_import_components() {
+        let mut templates = HashMap::new();
+        templates.insert("components.sato".to_string(), Template::from_str(r#"(components
+                                                                                (defcomponent button (href) (a (@ (class btn) (href $href)) (children)))
+                                                                                (defcomponent site-nav () (nav (button (@ (href "/")) "home"))))"#).unwrap());
+        let renderer = Renderer::builder()
+            .loader(Box::new(templates))
+            .build();
+        let expr = r#"(html (import "components.sato") (body (site-nav) (button (@ (href "/x")) "x")))"#;
+        let template = Template::from_str(expr).unwrap();
+        let html = renderer.render(&template, &RenderContext::default()).unwrap();
+        assert_eq!(html, r#"<!DOCTYPE html><html><body><nav><a class="btn" href="/">home</a></nav><a class="btn" href="/x">x</a></body></html>"#)
+    }
+
+    #[test]
+    fn test_import_loaded_once_per_render() {
+        struct Counting(Arc<AtomicUsize>);
+
+        impl TemplateLoader for Counting {
+            fn load(&self, _name: &str) -> Result<Template, TemplateError> {
+                self.0.fetch_add(1, Ordering::Relaxed);
+                Template::from_str("(components (defcomponent item (v) (li $v)))")
+            }
+        }
+
+        let loads = Arc::new(AtomicUsize::new(0));
+        let renderer = Renderer::builder()
+            .loader(Box::new(Counting(loads.clone())))
+            .build();
+        let template = Template::from_str(r#"(ul (for i in $items (import "components.sato") (item (@ (v $i)))))"#).unwrap();
+        let context = RenderContext::builder()
+            .insert("items", vec![1, 2, 3])
+            .build();
+        let expected = "<ul><li>1</li><li>2</li><li>3</li></ul>";
+        assert_eq!(renderer.render(&template, &context).unwrap(), expected);
+        assert_eq!(loads.load(Ordering::Relaxed), 1);
+        assert_eq!(renderer.render_compiled(&renderer.compile(&template), &context).unwrap(), expected);
+        assert_eq!(loads.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn test_let() {
+        let renderer = Renderer::builder()
+            .build();
+        let expr = r#"(html (let (($author (get $post author)) (name $author.name) (greeting "hi " $name)) (div $name) (div $greeting)) $name)"#;
+        let template = Template::from_str(expr).unwrap();
+        let author = RenderContext::builder()
+            .insert("name", "jake")
+            .build();
+        let post = RenderContext::builder()
+            .insert("author", author)
+            .build();
+        let context = RenderContext::builder()
+            .insert("post", post)
+            .build();
+        let html = renderer.render(&template, &context).unwrap();
+        assert_eq!(html, r#"<!DOCTYPE html><html><div>jake</div><div>hi jake</div>$name</html>"#)
+    }
+
+    #[test]
+    fn test_let_shadowing() {
+        let renderer = Renderer::builder()
+            .build();
+        let expr = r#"(div (let ((a (+ $a 1)) (a (* $a 10))) $a) " " $a)"#;
+        let template = Template::from_str(expr).unwrap();
+        let context = RenderContext::builder()
+            .insert("a", 1)
+            .build();
+        let html = renderer.render(&template, &context).unwrap();
+        assert_eq!(html, r#"<div>20 1</div>"#)
+    }
+
+    #[test]
+    fn test_let_errors() {
+        let renderer = Renderer::builder()
+            .build();
+        for expr in [r#"(let $a $a)"#, r#"(let ((a)) $a)"#, r#"(let (a 1) $a)"#, r#"(let ((a 1) b) $a)"#] {
+            let template = Template::from_str(expr).unwrap();
+            let err = renderer.render(&template, &RenderContext::default()).unwrap_err();
+            assert!(matches!(err.root(), RenderError::Let(..)), "{}", expr);
+        }
+    }
+
+    #[test]
+    fn test_and_or_not() {
+        let renderer = Renderer::builder()
+            .build();
+        let expr = r#"(div (if (and (is-set $a) (gt $a 1) $list) yes no) (if (or (eq $a 0) (not $empty)) yes no) (if (and) yes no) (if (or) yes no))"#;
+        let template = Template::from_str(expr).unwrap();
+        let context = RenderContext::builder()
+            .insert("a", 2)
+            .insert("list", vec![1])
+            .insert("empty", Vec::<i64>::new())
+            .build();
+        let html = renderer.render(&template, &context).unwrap();
+        assert_eq!(html, r#"<div>yesyesyesno</div>"#)
+    }
+
+    #[test]
+    fn test_and_or_short_circuit() {
+        let renderer = Renderer::builder()
+            .function("boom", Box::new(|_, _, _, _| {
+                Err(RenderError::Evaluate("should not be evaluated".into()))
+            }))
+            .build();
+        let expr = r#"(div (and 0 (boom)) (or 1 (boom)))"#;
+        let template = Template::from_str(expr).unwrap();
+        let html = renderer.render(&template, &RenderContext::default()).unwrap();
+        assert_eq!(html, r#"<div>falsetrue</div>"#)
+    }
+
+    #[test]
+    fn test_truthiness() {
+        let obj = std::collections::HashMap::from([("a".to_string(), RenderValue::Integer(1))]);
+        assert!(RenderValue::from("a").is_truthy());
+        assert!(!RenderValue::from("").is_truthy());
+        assert!(!RenderValue::Safe("".into()).is_truthy());
+        assert!(RenderValue::from(-1).is_truthy());
+        assert!(!RenderValue::from(0).is_truthy());
+        assert!(!RenderValue::from(false).is_truthy());
+        assert!(RenderValue::from(vec![0]).is_truthy());
+        assert!(!RenderValue::Vec(Vec::new()).is_truthy());
+        assert!(RenderValue::Object(obj).is_truthy());
+        assert!(!RenderValue::Object(Default::default()).is_truthy());
+        assert!(!RenderValue::Empty.is_truthy());
+    }
+
+    #[test]
+    fn test_cond() {
+        let renderer = Renderer::builder()
+            .build();
+        let expr = r#"(ul (for i in (range 0 4) (li (cond ((eq $i 0) "zero" "!") ((lt $i 2) "one") ($big "big") (else "other " $i)))))"#;
+        let template = Template::from_str(expr).unwrap();
+        let context = RenderContext::builder()
+            .insert("big", false)
+            .build();
+        let html = renderer.render(&template, &context).unwrap();
+        assert_eq!(html, r#"<ul><li>zero!</li><li>one</li><li>other 2</li><li>other 3</li></ul>"#)
+    }
+
+    #[test]
+    fn test_cond_without_match() {
+        let renderer = Renderer::builder()
+            .build();
+        let expr = r#"(div (cond ((eq 1 2) "no")))"#;
+        let template = Template::from_str(expr).unwrap();
+        let html = renderer.render(&template, &RenderContext::default()).unwrap();
+        assert_eq!(html, r#"<div></div>"#)
+    }
+
+    #[test]
+    fn test_when_unless() {
+        let renderer = Renderer::builder()
+            .build();
+        let expr = r#"(div (when $a (span 1) (span 2)) (unless $a (span 3)) (when $b (span 4)) (unless $b (span 5) (span 6)))"#;
+        let template = Template::from_str(expr).unwrap();
+        let context = RenderContext::builder()
+            .insert("a", true)
+            .insert("b", 0)
+            .build();
+        let html = renderer.render(&template, &context).unwrap();
+        assert_eq!(html, r#"<div><span>1</span><span>2</span><span>5</span><span>6</span></div>"#)
+    }
+
+
+    #[test]
+    fn test_node_spans() {
+        let template = Template::from_str("(html\n  (body (@ (class a))\n    ; comment (\n    \"some text\" 12))").unwrap();
+        let body = crate::template::get_children_by_tag(std::slice::from_ref(&template.expr), "body").remove(0);
+        assert_eq!((body.span.line(), body.span.column()), (2, 3));
+        assert_eq!((body.attrs[0].0.span().line(), body.attrs[0].0.span().column()), (2, 13));
+        assert_eq!((body.children[0].span().line(), body.children[0].span().column()), (4, 5));
+        assert_eq!((body.children[1].span().line(), body.children[1].span().column()), (4, 17));
+    }
+
+    #[test]
+    fn test_parse_error_span() {
+        let err = Template::from_named_str("broken.sato", "(html\n  (body (1 2)))").unwrap_err();
+        let TemplateError::ParseExprError(err) = err else { panic!("{:?}", err) };
+        assert_eq!(err.to_string(), "list does not start with a tag name\n --> broken.sato:2:9\n  |\n2 |   (body (1 2)))\n  |         ^");
+
+        let err = Template::from_str("(html\n  (body \"unterminated))").unwrap_err();
+        let TemplateError::ParseError(_, span) = err else { panic!("{:?}", err) };
+        assert_eq!((span.file(), span.line()), (None, 2));
+    }
+
+    #[test]
+    fn test_render_error_span() {
+        let renderer = Renderer::default();
+        let template = Template::from_str("(html\n\t(body\n\t\t(br (div))))").unwrap();
+        let err = renderer.render(&template, &RenderContext::default()).unwrap_err();
+        assert!(matches!(err.root(), RenderError::VoidElement(tag) if tag == "br"));
+        let span = err.span().unwrap();
+        assert_eq!((span.line(), span.column()), (3, 3));
+        assert_eq!(err.to_string(), "void element `br` cannot have children\n --> <template>:3:3\n  |\n3 | \t\t(br (div))))\n  | \t\t^");
+    }
+
+    #[test]
+    fn test_included_error_span() {
+        let mut templates = HashMap::new();
+        templates.insert("nav.sato".to_string(), Template::from_named_str("nav.sato", "(nav\n  (if))").unwrap());
+        let renderer = Renderer::builder()
+            .loader(Box::new(templates))
+            .build();
+        let template = Template::from_str(r#"(div (include "nav.sato"))"#).unwrap();
+        let err = renderer.render(&template, &RenderContext::default()).unwrap_err();
+        assert!(matches!(err.root(), RenderError::If(..)));
+        let span = err.span().unwrap();
+        assert_eq!((span.file(), span.line(), span.column()), (Some("nav.sato"), 2, 3));
+    }
+
+
+    #[test]
+    fn test_comments() {
+        let renderer = Renderer::default();
+        let template = Template::from_str(r#"
+; a line comment
+(div ; another (one
+  #| a block comment
+     with (unbalanced parens |# "a"
+  #| block comments #| can |# be nested |#
+  b) ; trailing
+"#).unwrap();
+        let html = renderer.render(&template, &RenderContext::default()).unwrap();
+        assert_eq!(html, "<div>ab</div>");
+    }
+
+    #[test]
+    fn test_string_escapes() {
+        let template = Template::from_str(r#"(div "a \"quoted\"\\ \t\n \u{e9}")"#).unwrap();
+        let TemplateExprNode::Tag(tag) = &template.expr else { panic!() };
+        assert!(matches!(&tag.children[0], TemplateExprNode::String(s, _) if s == "a \"quoted\"\\ \t\n \u{e9}"));
+
+        let err = Template::from_str(r#"(div "\q")"#).unwrap_err();
+        assert!(matches!(&err, TemplateError::ParseError(msg, span) if msg == "unknown escape `\\q`" && span.column() == 7), "{}", err);
+        let err = Template::from_str(r#"(div "\u{110000}")"#).unwrap_err();
+        assert!(matches!(&err, TemplateError::ParseError(msg, _) if msg == "invalid unicode escape"), "{}", err);
+    }
+
+    #[test]
+    fn test_numeric_literals() {
+        let template = Template::from_str("(div 12 -3 1.5 2e3 12px inf)").unwrap();
+        let TemplateExprNode::Tag(tag) = &template.expr else { panic!() };
+        assert!(matches!(tag.children[0], TemplateExprNode::Integer(12, _)));
+        assert!(matches!(tag.children[1], TemplateExprNode::Integer(-3, _)));
+        assert!(matches!(tag.children[2], TemplateExprNode::Float(f, _) if f == 1.5));
+        assert!(matches!(tag.children[3], TemplateExprNode::Float(f, _) if f == 2000.0));
+        assert!(matches!(&tag.children[4], TemplateExprNode::Identifier(s, _) if s == "12px"));
+        assert!(matches!(&tag.children[5], TemplateExprNode::Identifier(s, _) if s == "inf"));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        for (expr, message, line, column) in [
+            ("(div\n  (span \"a)", "unterminated string", 2, 9),
+            ("(div\n  (span a)", "unclosed `(`", 1, 1),
+            ("(div) (span)", "unexpected input after the template", 1, 7),
+            ("(div))", "unexpected input after the template", 1, 6),
+            ("  ; nothing\n", "unexpected end of template", 2, 1),
+            ("(div #| a", "unterminated block comment", 1, 6),
+        ] {
+            let err = Template::from_str(expr).unwrap_err();
+            let TemplateError::ParseError(msg, span) = &err else { panic!("{:?}", err) };
+            assert_eq!((msg.as_str(), span.line(), span.column()), (message, line, column), "{}", expr);
+        }
+
+        let err = Template::from_str("(div (@ (class a) b))").unwrap_err();
+        assert!(matches!(&err, TemplateError::ParseExprError(ParseExprError::NotAnAttribute(span)) if span.column() == 19), "{}", err);
+        let err = Template::from_str("(div (@ ()))").unwrap_err();
+        assert!(matches!(&err, TemplateError::ParseExprError(ParseExprError::AttributeMissingElement(_))), "{}", err);
+    }
+
+
+    #[test]
+    fn test_string_literals_are_not_expanded() {
+        let renderer = Renderer::default();
+        let expr = r#"(div (@ (title "$price")) "$price" $price (if (eq "$price" $price) same different))"#;
+        let template = Template::from_str(expr).unwrap();
+        let context = RenderContext::builder()
+            .insert("price", "5")
+            .build();
+        let html = renderer.render(&template, &context).unwrap();
+        assert_eq!(html, r#"<div title="$price">$price5different</div>"#);
+
+        let template = Template::from_str(r#"(is-set "$price")"#).unwrap();
+        let err = renderer.render(&template, &context).unwrap_err();
+        assert!(matches!(err.root(), RenderError::IsSet(..)));
+    }
+
+    #[test]
+    fn test_case_evaluates_condition() {
+        let renderer = Renderer::default();
+        let expr = r#"(div (switch $a (case "x y" quoted) (case $b variable) (case 3 integer) (case b symbol)))"#;
+        let template = Template::from_str(expr).unwrap();
+        for (a, expected) in [
+            (ContextValue::from("x y"), "quoted"),
+            (ContextValue::from("z"), "variable"),
+            (ContextValue::from(3), "integer"),
+            (ContextValue::from("b"), "symbol"),
+            (ContextValue::from("$b"), ""),
+        ] {
+            let context = RenderContext::builder()
+                .insert("a", a)
+                .insert("b", "z")
+                .build();
+            let html = renderer.render(&template, &context).unwrap();
+            assert_eq!(html, format!("<div>{}</div>", expected));
+        }
+    }
+
+    #[test]
+    fn test_switch_value_is_not_in_context() {
+        let renderer = Renderer::builder()
+            .strict(true)
+            .build();
+        let template = Template::from_str("(div (switch 1 (case 1 $__switch)))").unwrap();
+        let err = renderer.render(&template, &RenderContext::default()).unwrap_err();
+        assert!(matches!(err.root(), RenderError::UndefinedVariable(name) if name == "__switch"));
+
+        let template = Template::from_str("(div (switch 1 (case 1 a)) (case 1 b))").unwrap();
+        let err = renderer.render(&template, &RenderContext::default()).unwrap_err();
+        assert!(matches!(err.root(), RenderError::Case(_)));
+    }
+
+
+    #[test]
+    fn test_strict_undefined_variables() {
+        let context = RenderContext::builder()
+            .insert("post", RenderContext::builder().insert("title", "hello").build())
+            .insert("name", "me")
+            .build();
+        let lenient = Renderer::default();
+        let strict = Renderer::builder()
+            .strict(true)
+            .build();
+
+        let template = Template::from_str(r#"(div $name $post.title)"#).unwrap();
+        assert_eq!(strict.render(&template, &context).unwrap(), "<div>mehello</div>");
+
+        for (expr, path) in [
+            ("(div $nmae)", "nmae"),
+            ("(div $post.titel)", "post.titel"),
+            ("(div $post.title.length)", "post.title.length"),
+            ("(div (@ (class $missing)))", "missing"),
+        ] {
+            let template = Template::from_str(expr).unwrap();
+            assert!(lenient.render(&template, &context).is_ok(), "{}", expr);
+            let err = strict.render(&template, &context).unwrap_err();
+            assert!(matches!(err.root(), RenderError::UndefinedVariable(p) if p == path), "{}: {}", expr, err);
+        }
+
+        let template = Template::from_str("(for i in $items (span $i))").unwrap();
+        let err = strict.render(&template, &context).unwrap_err();
+        assert!(matches!(err.root(), RenderError::UndefinedVariable(p) if p == "items"));
+    }
+
+    #[test]
+    fn test_default() {
+        let context = RenderContext::builder()
+            .insert("post", RenderContext::builder().insert("title", "hello").build())
+            .insert("empty", "")
+            .build();
+        let strict = Renderer::builder()
+            .strict(true)
+            .build();
+
+        let expr = r#"(div (default $post.title "untitled") (default $post.subtitle "no " (span subtitle)) (default $empty "x") (if (is-set $post.author) author anonymous))"#;
+        let template = Template::from_str(expr).unwrap();
+        assert_eq!(strict.render(&template, &context).unwrap(), "<div>hellono <span>subtitle</span>anonymous</div>");
+        assert_eq!(Renderer::default().render(&template, &context).unwrap(), "<div>hellono <span>subtitle</span>anonymous</div>");
+
+        let template = Template::from_str("(default)").unwrap();
+        let err = strict.render(&template, &context).unwrap_err();
+        assert!(matches!(err.root(), RenderError::Default(..)));
+    }
+
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serialize_context() {
+        use serde::Serialize;
+
+        #[derive(Serialize)]
+        struct Author {
+            name: String,
+            email: Option<String>,
+        }
+
+        #[derive(Serialize)]
+        enum Status {
+            Draft,
+            Published { year: u16 },
+            Tagged(Vec<&'static str>),
+        }
+
+        #[derive(Serialize)]
+        struct Post {
+            title: &'static str,
+            subtitle: Option<&'static str>,
+            views: u64,
+            author: Author,
+            statuses: Vec<Status>,
+            scores: std::collections::BTreeMap<u32, bool>,
+        }
+
+        let post = Post {
+            title: "hello",
+            subtitle: None,
+            views: 12,
+            author: Author { name: "me".into(), email: None },
+            statuses: vec![Status::Draft, Status::Published { year: 2022 }, Status::Tagged(vec!["a", "b"])],
+            scores: [(1, true), (2, false)].into(),
+        };
+        let context = RenderContext::from_serialize(&post).unwrap();
+        assert!(context.get("subtitle").is_none());
+        assert_eq!(context.get("views"), Some(&ContextValue::Integer(12)));
+
+        let renderer = Renderer::default();
+        let expr = r#"(div $title $author.name (is-set $author.email) (get $statuses 0) (get (get (get $statuses 1) Published) year) (get (get $statuses 2) Tagged) (for k v in $scores (i $k $v)))"#;
+        let template = Template::from_str(expr).unwrap();
+        let html = renderer.render(&template, &context).unwrap();
+        assert_eq!(html, "<div>hellomefalseDraft2022ab<i>1true</i><i>2false</i></div>");
+
+        assert!(matches!(RenderContext::from_serialize(&vec![1, 2]), Err(crate::SerializeError::NotAnObject)));
+        assert!(matches!(crate::to_context_value(&u64::MAX), Err(crate::SerializeError::IntegerOutOfRange(_))));
+        let mut bad_keys = HashMap::new();
+        bad_keys.insert(vec![1], 1);
+        assert!(matches!(crate::to_context_value(&bad_keys), Err(crate::SerializeError::KeyMustBeString)));
+    }
+
+
+    #[cfg(all(feature = "json", feature = "toml", feature = "yaml"))]
+    #[test]
+    fn test_context_from_data_files() {
+        use crate::ContextError;
+
+        let renderer = Renderer::default();
+        let template = Template::from_str(r#"(div $site.title " " $site.missing (for p in $posts (span $p.title (is-set $p.draft))))"#).unwrap();
+        let expected = "<div>my site false<span>firstfalse</span><span>secondtrue</span></div>";
+
+        let json = RenderContext::from_json_str(r#"{"site": {"title": "my site", "missing": null}, "posts": [{"title": "first", "draft": null}, {"title": "second", "draft": true}]}"#).unwrap();
+        assert_eq!(renderer.render(&template, &json).unwrap(), expected);
+
+        let toml = RenderContext::from_toml_str("posts = [{ title = \"first\" }, { title = \"second\", draft = true }]\n[site]\ntitle = \"my site\"\nupdated = 1979-05-27T07:32:00Z\n").unwrap();
+        assert_eq!(renderer.render(&template, &toml).unwrap(), expected);
+        assert!(matches!(toml.get("site"), Some(ContextValue::Object(site)) if site.get("updated") == Some(&ContextValue::from("1979-05-27T07:32:00Z"))));
+
+        let yaml = RenderContext::from_yaml_str("site:\n  title: my site\nposts:\n  - title: first\n  - title: second\n    draft: true\n").unwrap();
+        assert_eq!(renderer.render(&template, &yaml).unwrap(), expected);
+
+        let dumped = RenderContext::from_json_str(&json.to_json_string().unwrap()).unwrap();
+        assert_eq!(renderer.render(&template, &dumped).unwrap(), expected);
+        let dumped = RenderContext::from_toml_str(&toml.to_toml_string().unwrap()).unwrap();
+        assert_eq!(renderer.render(&template, &dumped).unwrap(), expected);
+        let dumped = RenderContext::from_yaml_str(&yaml.to_yaml_string().unwrap()).unwrap();
+        assert_eq!(renderer.render(&template, &dumped).unwrap(), expected);
+
+        assert!(matches!(RenderContext::from_json_str("[1, 2]"), Err(ContextError::NotAnObject)));
+        let err = RenderContext::from_json_str(r#"{"a": 18446744073709551615}"#).unwrap_err();
+        assert!(err.to_string().contains("does not fit in an i64"), "{}", err);
+        assert!(matches!(RenderContext::from_json_str("{"), Err(ContextError::Json(_))));
+        assert!(matches!(RenderContext::from_path("context.ini"), Err(ContextError::UnknownFormat(_))));
+        assert!(matches!(RenderContext::from_path("missing.toml"), Err(ContextError::Io(..))));
+
+        let path = std::env::temp_dir().join(format!("sato-context-{}.yml", std::process::id()));
+        std::fs::write(&path, "posts: []\nsite:\n  title: from a file\n").unwrap();
+        let context = RenderContext::from_path(&path).unwrap();
+        assert_eq!(renderer.render(&template, &context).unwrap(), "<div>from a file false</div>");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+
+    #[cfg(feature = "toml")]
+    #[test]
+    fn test_null_context_to_toml() {
+        let context = RenderContext::builder()
+            .insert("title", "hi")
+            .insert("subtitle", ContextValue::Null)
+            .insert("author", RenderContext::builder().insert("email", None::<String>).build())
+            .build();
+        let toml = context.to_toml_string().unwrap();
+        let dumped = RenderContext::from_toml_str(&toml).unwrap();
+        assert_eq!(dumped.get("title"), Some(&ContextValue::from("hi")));
+        assert!(dumped.get("subtitle").is_none());
+        assert!(matches!(dumped.get("author"), Some(ContextValue::Object(author)) if author.get("email").is_none()));
+    }
+
+
+    #[test]
+    fn test_float_math() {
+        let renderer = Renderer::default();
+        let expr = r#"(div (+ 1 2) " " (+ 1 2.5) " " (* $price 3) " " (/ 7 2) " " (/ 7 2.0) " " (- $count 0.5) " " (% 7.5 2))"#;
+        let template = Template::from_str(expr).unwrap();
+        let context = RenderContext::builder()
+            .insert("price", 1.25)
+            .insert("count", 3)
+            .build();
+        let html = renderer.render(&template, &context).unwrap();
+        assert_eq!(html, "<div>3 3.5 3.75 3 3.5 2.5 1.5</div>");
+    }
+
+    #[test]
+    fn test_float_comparison() {
+        let renderer = Renderer::default();
+        let expr = r#"(div (eq 2 2.0) (lt 1 1.5) (gt $price 10) (gte 2.5 $price) (ne 0.1 0.2) (if 0.0 yes no) (switch $price (case 2.5 matched)))"#;
+        let template = Template::from_str(expr).unwrap();
+        let context = RenderContext::builder()
+            .insert("price", 2.5)
+            .build();
+        let html = renderer.render(&template, &context).unwrap();
+        assert_eq!(html, "<div>truetruefalsetruetruenomatched</div>");
+    }
+
+    #[test]
+    fn test_format_number() {
+        let renderer = Renderer::default();
+        let expr = r#"(div (format-number $price 2) " " (format-number 2.675 1) " " (format-number (/ 10 4.0)) " " (format-number 3 3))"#;
+        let template = Template::from_str(expr).unwrap();
+        let context = RenderContext::builder()
+            .insert("price", 12.5)
+            .build();
+        let html = renderer.render(&template, &context).unwrap();
+        assert_eq!(html, "<div>12.50 2.7 2 3.000</div>");
+
+        for expr in [r#"(format-number abc 2)"#, r#"(format-number 1.5 -1)"#, r#"(format-number)"#] {
+            let template = Template::from_str(expr).unwrap();
+            let err = renderer.render(&template, &context).unwrap_err();
+            assert!(matches!(err.root(), RenderError::FormatNumber(..)), "{}", expr);
+        }
+    }
+
+
+    #[test]
+    fn test_null_values() {
+        let renderer = Renderer::default();
+        let expr = r#"(div (@ (title $subtitle)) "[" $subtitle "]" (is-set $subtitle) (null? $subtitle) (is-null $title) (if $subtitle yes no) (default $subtitle untitled) (for a in $authors (i (null? $a))))"#;
+        let template = Template::from_str(expr).unwrap();
+        let context = RenderContext::builder()
+            .insert("title", Some("hello"))
+            .insert("subtitle", None::<String>)
+            .insert("authors", vec![Some("me"), None])
+            .build();
+        assert_eq!(context.get("subtitle"), Some(&ContextValue::Null));
+        let html = renderer.render(&template, &context).unwrap();
+        assert_eq!(html, "<div>[]truetruefalsenountitled<i>false</i><i>true</i></div>");
+
+        let strict = Renderer::builder()
+            .strict(true)
+            .build();
+        assert_eq!(strict.render(&template, &context).unwrap(), html);
+
+        let template = Template::from_str("(let ((a (if 0 x))) (null? $a))").unwrap();
+        assert_eq!(renderer.render(&template, &context).unwrap(), "true");
+        assert_eq!(RenderValue::from(None::<i64>), RenderValue::Null);
+        assert!(!RenderValue::Null.is_truthy());
+
+        let template = Template::from_str("(null? $a $b)").unwrap();
+        let err = renderer.render(&template, &context).unwrap_err();
+        assert!(matches!(err.root(), RenderError::IsNull(..)));
+    }
+
+
+    #[test]
+    fn test_render_to_writer() {
+        let renderer = Renderer::default();
+        let expr = r#"(html (head (title "a & b")) (body (for i in $items (p (@ (class $i)) $i)) (raw "<hr>") (br) (script "x < y")))"#;
+        let template = Template::from_str(expr).unwrap();
+        let context = RenderContext::builder()
+            .insert("items", vec!["a<", "b"])
+            .build();
+        let html = renderer.render(&template, &context).unwrap();
+
+        let mut bytes = Vec::new();
+        renderer.render_to_writer(&template, &context, &mut bytes).unwrap();
+        assert_eq!(String::from_utf8(bytes).unwrap(), html);
+
+        let mut text = String::from("prefix:");
+        renderer.render_to_fmt(&template, &context, &mut text).unwrap();
+        assert_eq!(text, format!("prefix:{}", html));
+
+        let xml = Renderer::builder()
+            .dialect(Dialect::Xml)
+            .build();
+        let template = Template::from_str(r#"(document (feed (title) (entry (id (if 0 x)) (name "b"))))"#).unwrap();
+        let mut text = String::new();
+        xml.render_to_fmt(&template, &context, &mut text).unwrap();
+        assert_eq!(text, xml.render(&template, &context).unwrap());
+        assert!(text.ends_with("<feed><title /><entry><id /><name>b</name></entry></feed>"), "{}", text);
+    }
+
+
+    #[test]
+    fn test_render_to_writer_errors() {
+        struct Full;
+        impl std::io::Write for Full {
+            fn write(&mut self, _: &[u8]) -> std::io::Result<usize> {
+                Err(std::io::Error::new(std::io::ErrorKind::WriteZero, "full"))
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let renderer = Renderer::default();
+        let template = Template::from_str(r#"(div "hello")"#).unwrap();
+        let err = renderer.render_to_writer(&template, &RenderContext::default(), &mut Full).unwrap_err();
+        assert!(matches!(err.root(), RenderError::Io(e) if e.kind() == std::io::ErrorKind::WriteZero));
+
+        let template = Template::from_str(r#"(div "before" (br "x") "after")"#).unwrap();
+        let mut bytes = Vec::new();
+        let err = renderer.render_to_writer(&template, &RenderContext::default(), &mut bytes).unwrap_err();
+        assert!(matches!(err.root(), RenderError::VoidElement(..)));
+        assert_eq!(bytes, b"<div>before");
+    }
+
+
+    #[test]
+    fn test_builtins_stream_their_output() {
+        let renderer = Renderer::builder()
+            .function("fail", Box::new(|_, _, _, _| Err(RenderError::UserDefined("fail".into(), "stop".into()))))
+            .template("row", Template::from_str("(li $i)").unwrap())
+            .build();
+        let context = RenderContext::builder()
+            .insert("items", vec![1, 2, 3])
+            .build();
+        // everything before the failure has already been written out
+        let cases = [
+            ("(ul (for i in $items (li $i) (when (eq $i 2) (fail))))", "<ul><li>1</li><li>2</li>"),
+            ("(ul (for i in $items (if (eq $i 2) (fail) (include row))))", "<ul><li>1</li>"),
+            ("(ul (let ((i 1)) (li $i) (fail)))", "<ul><li>1</li>"),
+            ("(ul (cond ((eq 1 1) (li 1) (fail))))", "<ul><li>1</li>"),
+            ("(ul (switch 1 (case 1 (li 1) (fail))))", "<ul><li>1</li>"),
+            ("(ul (defcomponent row () (li (children)) (fail)) (row 1))", "<ul><li>1</li>"),
+        ];
+        for (expr, written) in cases {
+            let template = Template::from_str(expr).unwrap();
+            let mut text = String::new();
+            let err = renderer.render_to_fmt(&template, &context, &mut text).unwrap_err();
+            assert!(matches!(err.root(), RenderError::UserDefined(..)), "{}", expr);
+            assert_eq!(text, written, "{}", expr);
+        }
+
+        // output limits stop a long loop as soon as they are reached
+        let renderer = Renderer::builder()
+            .max_output(100)
+            .build();
+        let template = Template::from_str(r#"(div (for i in (range 0 1000000000) (p "row")))"#).unwrap();
+        let err = renderer.render(&template, &context).unwrap_err();
+        assert!(matches!(err.root(), RenderError::OutputLimit(100)));
+    }
+
+
+    #[test]
+    fn test_compiled_template_matches_render() {
+        let context = RenderContext::builder()
+            .insert("title", "a < b")
+            .insert("n", 3)
+            .insert("on", true)
+            .insert("off", false)
+            .insert("items", vec!["x", "y"])
+            .insert("user", RenderContext::builder().insert("name", "jake").build())
+            .insert("nothing", None::<i64>)
+            .build();
+        let exprs = [
+            r#"(html (head (title $title)) (body (div (@ (class "a b") (id main)) "static & text" 1 2.5 (p (b hi))) (p $user.name $user.missing $user $missing)))"#,
+            r#"(html (@ (lang en)) (body (input (@ (disabled) (checked $on) (hidden $off) (value $nothing))) (br) (hr (@ (class $title)))))"#,
+            r#"(document (feed (title) (entry (id (if 0 x)) (name "b") (link (@ (href "/"))))))"#,
+            r#"(div (for i in $items (span $i)) (if (gt $n 2) big small) (+ $n 1) (let ((a 5)) $a) () (raw "<hr>"))"#,
+            r#"(div (defcomponent card (title) (section (h1 $title) (children))) (card (@ (title $title)) (p body)))"#,
+            r#"(div (script "var x = 1 < 2;") (style) (textarea (@ (rows 4))))"#,
+            r#"(html (@ (xmlns "urn:x") (class $title)) (body))"#,
+            r#"(div (br "x"))"#,
+            r#"(div (script "</script>"))"#,
+            r#"(ul (li $user.name) (li (get $items 9)))"#,
+            r#"(ul (for (enumerate n i) in $items (li (@ (class $i)) $n " " (if (eq $i x) (b "first") (when $on (i "rest"))))))"#,
+            r#"(dl (for k v in $user (dt $k) (dd $v)) (unless $off (p "no")) (when $off (p "yes")) (if $off x))"#,
+            r#"(div (cond ((eq $n 2) two) ($off off) (else (let ((a $n) (b (+ $a 1))) (span $a $b)))) (cond ($on)) (cond ((gt $n 9) big)))"#,
+            r#"(ul (for i in (range 0 $n) (defcomponent item (v) (li $v)) (item (@ (v $i)))))"#,
+            r#"(div (for i in $n (p $i)) (if) (cond x) (let (x) y))"#,
+            r#"(div (with ((a 1)) (for i in $items (if $i (p $a $i)))))"#,
+            r#"$title"#,
+            r#""just text""#,
+        ];
+        for dialect in [Dialect::Html5, Dialect::Xhtml, Dialect::Xml] {
+            for strict in [false, true] {
+                let renderer = Renderer::builder()
+                    .dialect(dialect)
+                    .strict(strict)
+                    .build();
+                for expr in exprs {
+                    let template = Template::from_str(expr).unwrap();
+                    let compiled = renderer.compile(&template);
+                    let expected = renderer.render(&template, &context).map_err(|err| err.to_string());
+                    let actual = renderer.render_compiled(&compiled, &context).map_err(|err| err.to_string());
+                    assert_eq!(actual, expected, "{:?} {} {}", dialect, strict, expr);
+
+                    let mut bytes = Vec::new();
+                    let result = renderer.render_compiled_to_writer(&compiled, &context, &mut bytes);
+                    if let Ok(expected) = expected {
+                        result.unwrap();
+                        assert_eq!(String::from_utf8(bytes).unwrap(), expected);
+                    }
+                }
+            }
+        }
+    }
+
+
+    #[test]
+    fn test_compiled_template_renderers() {
+        let html = Renderer::default();
+        let xml = Renderer::builder()
+            .dialect(Dialect::Xml)
+            .build();
+        let template = Template::from_str(r#"(feed (title) (upper hi))"#).unwrap();
+        let compiled = html.compile(&template);
+        assert_eq!(html.render_compiled(&compiled, &RenderContext::default()).unwrap(), "<feed><title></title><upper>hi</upper></feed>");
+        // rendered from the source by any other renderer
+        assert_eq!(xml.render_compiled(&compiled, &RenderContext::default()).unwrap(), "<feed><title /><upper>hi</upper></feed>");
+
+        let custom = Renderer::builder()
+            .function("upper", Box::new(|_attrs, expr, renderer, context| {
+                Ok(RenderValue::String(renderer.evaluate_multiple(expr, context)?.into_text().to_uppercase()))
+            }))
+            .replace("html", Box::new(|_attrs, _expr, _renderer, _context| Ok(RenderValue::Safe("replaced".into()))))
+            .build();
+        let compiled = custom.compile(&template);
+        assert_eq!(custom.render_compiled(&compiled, &RenderContext::default()).unwrap(), "<feed><title></title>HI</feed>");
+        let compiled = custom.compile(&Template::from_str("(html (body))").unwrap());
+        assert_eq!(custom.render_compiled(&compiled, &RenderContext::default()).unwrap(), "replaced");
+        assert_eq!(compiled.template().expr.span().line(), 1);
+    }
+
+
+    #[test]
+    fn test_compiled_builtin_bodies() {
+        // the static parts of the body are rendered once when compiling, so a loop costs far
+        // fewer steps than when it is evaluated from the template
+        let renderer = Renderer::builder()
+            .fuel(30)
+            .build();
+        let context = RenderContext::builder()
+            .insert("items", vec![1, 2, 3, 4, 5, 6])
+            .build();
+        let template = Template::from_str(r#"(ul (for i in $items (when true (li (@ (class "row")) (b "static") (i "text")))))"#).unwrap();
+        let err = renderer.render(&template, &context).unwrap_err();
+        assert!(matches!(err.root(), RenderError::FuelExhausted(30)));
+        let html = renderer.render_compiled(&renderer.compile(&template), &context).unwrap();
+        assert_eq!(html, r#"<ul>"#.to_string() + &r#"<li class="row"><b>static</b><i>text</i></li>"#.repeat(6) + "</ul>");
+    }
+
+
+    // the errors from rendering a template that is expected to fail, first as it is and then
+    // compiled
+    fn render_errors(renderer: &Renderer, expr: &str, context: &RenderContext) -> [RenderError; 2] {
+        let template = Template::from_str(expr).unwrap();
+        [renderer.render(&template, context).unwrap_err(),
+         renderer.render_compiled(&renderer.compile(&template), context).unwrap_err()]
+    }
+
+    fn error_context() -> RenderContext {
+        RenderContext::builder()
+            .insert("a", vec!["asd", "qwe"])
+            .insert("max", i64::MAX)
+            .insert("min", i64::MIN)
+            .build()
+    }
+
+    #[test]
+    fn test_get_errors() {
+        let renderer = Renderer::default();
+        let context = error_context();
+        for expr in ["(get)", "(get $a)", "(get $a -1)", "(get $a 5)", "(get (get) 0)"] {
+            for err in render_errors(&renderer, expr, &context) {
+                assert!(matches!(err.root(), RenderError::Get(_)), "{}: {:?}", expr, err);
+            }
+        }
+    }
+
+    #[test]
+    fn test_get_missing_key() {
+        let renderer = Renderer::default();
+        let context = RenderContext::builder()
+            .insert("post", RenderContext::builder().insert("title", "hello").build())
+            .build();
+        for err in render_errors(&renderer, "(get $post author)", &context) {
+            assert!(matches!(err.root(), RenderError::Get(message) if message == "no key `author` in object"), "{:?}", err);
+        }
+    }
+
+    #[test]
+    fn test_comparison_errors() {
+        let renderer = Renderer::default();
+        let context = error_context();
+        for expr in ["(eq (get $a 5) 1)", "(eq 1 (get $a 5))"] {
+            for err in render_errors(&renderer, expr, &context) {
+                assert!(matches!(err.root(), RenderError::Get(_)), "{}: {:?}", expr, err);
+            }
+        }
+        for err in render_errors(&renderer, "(eq)", &context) {
+            assert!(matches!(err.root(), RenderError::Cmp(_)), "{:?}", err);
+        }
+    }
+
+    #[test]
+    fn test_division_by_zero() {
+        let renderer = Renderer::default();
+        let context = error_context();
+        for expr in ["(/ 1 0)", "(% $max 0)"] {
+            for err in render_errors(&renderer, expr, &context) {
+                assert!(matches!(err.root(), RenderError::DivisionByZero), "{}: {:?}", expr, err);
+            }
+        }
+    }
+
+    #[test]
+    fn test_integer_overflow() {
+        let renderer = Renderer::default();
+        let context = error_context();
+        for expr in ["(+ $max 1)", "(- $min 1)", "(* $max 2)", "(/ $min -1)", "(% $min -1)"] {
+            for err in render_errors(&renderer, expr, &context) {
+                assert!(matches!(err.root(), RenderError::Overflow), "{}: {:?}", expr, err);
+            }
+        }
+
+        let template = Template::from_str("(div (/ 7 2) (/ 7.0 2) (% -7 2) (+ $max 0))").unwrap();
+        assert_eq!(renderer.render(&template, &context).unwrap(), "<div>33.5-19223372036854775807</div>");
+    }
+
+    #[test]
+    fn test_range_step_errors() {
+        let renderer = Renderer::default();
+        let context = error_context();
+        for err in render_errors(&renderer, "(for i in (range 0 5 0) $i)", &context) {
+            assert!(matches!(err.root(), RenderError::RangeStep(0)), "{:?}", err);
+        }
+        for err in render_errors(&renderer, "(for i in (range 0 5 -2) $i)", &context) {
+            assert!(matches!(err.root(), RenderError::RangeStep(-2)), "{:?}", err);
+        }
+        for err in render_errors(&renderer, r#"(for i in (range 0 10 "x") $i)"#, &context) {
+            assert!(matches!(err.root(), RenderError::For(_)), "{:?}", err);
+        }
+    }
+
+    #[test]
+    fn test_math_operand_errors() {
+        let renderer = Renderer::default();
+        for expr in ["(+ 1)", r#"(+ 1 "x")"#, r#"(* "x" 2)"#] {
+            for err in render_errors(&renderer, expr, &error_context()) {
+                assert!(matches!(err.root(), RenderError::Math(_)), "{}: {:?}", expr, err);
+            }
+        }
+    }
+
+    #[test]
+    fn test_for_without_variable() {
+        let renderer = Renderer::default();
+        for err in render_errors(&renderer, "(for in $a x)", &error_context()) {
+            assert!(matches!(err.root(), RenderError::For(_)), "{:?}", err);
+        }
+    }
+
+    #[test]
+    fn test_format_number_decimals_error() {
+        let renderer = Renderer::default();
+        for err in render_errors(&renderer, "(format-number 1.5 1000000)", &error_context()) {
+            assert!(matches!(err.root(), RenderError::FormatNumber(_)), "{:?}", err);
+        }
+    }
+
+
+    // leaves of generated templates. whole objects are left out since their values are output
+    // in no particular order
+    const FUZZ_ATOMS: &[&str] = &[
+        "0", "1", "-1", "7", "2.5", "-0.0", "1e308", "9223372036854775807", "-9223372036854775808",
+        "in", "else", "x", "\"s\"", "\"<&>\"", "\"</script>\"", "()", "true", "false",
+        "$items", "$n", "$zero", "$max", "$min", "$f", "$user.name", "$user.age", "$user.missing",
+        "$missing", "$nothing", "$html", "$template", "$items.0",
+    ];
+
+    const FUZZ_TAGS: &[&str] = &[
+        "html", "document", "div", "p", "br", "script", "style", "", "if", "when", "unless", "cond",
+        "else", "and", "or", "not", "switch", "case", "for", "enumerate", "get", "is-set", "default",
+        "null?", "raw", "let", "with", "block", "super", "extends", "include", "import", "children",
+        "eq", "ne", "lt", "gt", "lte", "gte", "+", "-", "*", "/", "%", "format-number", "range",
+        "defcomponent", "x",
+    ];
+
+    fn fuzz_context() -> RenderContext {
+        RenderContext::builder()
+            .insert("items", vec!["a", "$n", "c"])
+            .insert("n", 3)
+            .insert("zero", 0)
+            .insert("max", i64::MAX)
+            .insert("min", i64::MIN)
+            .insert("f", 0.1)
+            .insert("user", RenderContext::builder().insert("name", "jake").insert("age", -1).build())
+            .insert("nothing", None::<String>)
+            .insert("html", ContextValue::Safe("<b>hi</b>".into()))
+            .insert("template", Template::from_str("(i $n $user.name)").unwrap())
+            .build()
+    }
+
+    fn fuzz_template() -> impl proptest::strategy::Strategy<Value = String> {
+        use proptest::prelude::*;
+        let atom = proptest::sample::select(FUZZ_ATOMS).prop_map(String::from);
+        atom.clone().prop_recursive(4, 48, 5, move |inner| {
+            let attr = (proptest::sample::select(&["class", "id", "disabled", "$n"][..]), proptest::collection::vec(inner.clone(), 0..3))
+                .prop_map(|(name, values)| format!("({} {})", name, values.join(" ")));
+            (proptest::sample::select(FUZZ_TAGS), proptest::collection::vec(attr, 0..3), proptest::collection::vec(inner, 0..5))
+                .prop_map(|(tag, attrs, children)| {
+                    let attrs = if attrs.is_empty() { String::new() } else { format!(" (@ {})", attrs.join(" ")) };
+                    format!("({}{} {})", tag, attrs, children.join(" "))
+                })
+        })
+    }
+
+    fn render_every_way(text: &str) {
+        let template = match Template::from_str(text) {
+            Ok(template) => template,
+            Err(_) => return,
+        };
+        let context = fuzz_context();
+        let limited = |builder: RendererBuilder| {
+            builder
+                .max_iterations(100)
+                .max_output(100_000)
+                .fuel(20_000)
+                .build()
+        };
+        for renderer in [limited(Renderer::builder()), limited(Renderer::builder().strict(true)), limited(Renderer::builder().dialect(Dialect::Xml))] {
+            let rendered = renderer.render(&template, &context);
+            let compiled = renderer.render_compiled(&renderer.compile(&template), &context);
+            // compiled templates take fewer steps, so only one of them might run out
+            let out_of_fuel = |result: &Result<String, RenderError>| matches!(result, Err(err) if matches!(err.root(), RenderError::FuelExhausted(_)));
+            if !out_of_fuel(&rendered) && !out_of_fuel(&compiled) {
+                assert_eq!(rendered.map_err(|err| err.to_string()), compiled.map_err(|err| err.to_string()), "{}", text);
+            }
+        }
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn fuzz_render_generated_templates(text in fuzz_template()) {
+            render_every_way(&text);
+        }
+
+        #[test]
+        fn fuzz_render_arbitrary_text(text in r#"[()@$"\;#|a-z0-9.\- \n\t<>&é]{0,120}"#) {
+            render_every_way(&text);
+        }
+
+        #[test]
+        fn fuzz_parse_any_string(text in ".{0,200}") {
+            let _ = Template::from_str(&text);
+        }
+    }
+
+    fn looping_context() -> RenderContext {
+        RenderContext::builder()
+            .insert("page", Template::from_str("(div $page)").unwrap())
+            .insert("items", vec![1, 2, 3, 4, 5])
+            .build()
+    }
+
+    #[test]
+    fn test_depth_limit() {
+        let renderer = Renderer::default();
+        for expr in ["(p $page)", "(div (defcomponent tree () (ul (tree))) (tree))"] {
+            for err in render_errors(&renderer, expr, &looping_context()) {
+                assert!(matches!(err.root(), RenderError::DepthLimit(128)), "{}: {:?}", expr, err);
+            }
+        }
+        let renderer = Renderer::builder()
+            .max_depth(3)
+            .build();
+        for err in render_errors(&renderer, "(a (b (c (d $items))))", &looping_context()) {
+            assert!(matches!(err.root(), RenderError::DepthLimit(3)), "{:?}", err);
+        }
+    }
+
+    #[test]
+    fn test_parse_nesting_limit() {
+        let nested = format!("{}{}", "(div ".repeat(300), ")".repeat(300));
+        let err = Template::from_str(&nested).unwrap_err();
+        assert!(matches!(err, TemplateError::ParseError(message, _) if message == "lists are nested more than 256 deep"));
+    }
+
+    #[test]
+    fn test_iteration_limit() {
+        let renderer = Renderer::builder()
+            .max_iterations(4)
+            .build();
+        for err in render_errors(&renderer, "(for i in $items $i)", &looping_context()) {
+            assert!(matches!(err.root(), RenderError::IterationLimit(4)), "{:?}", err);
+        }
+        // ranges are checked before they are made
+        let renderer = Renderer::builder()
+            .max_iterations(1000)
+            .build();
+        for expr in ["(for i in (range 0 1000000000) $i)", "(for i in (range -9223372036854775808 9223372036854775807 2) $i)"] {
+            for err in render_errors(&renderer, expr, &looping_context()) {
+                assert!(matches!(err.root(), RenderError::IterationLimit(1000)), "{}: {:?}", expr, err);
+            }
+        }
+    }
+
+    #[test]
+    fn test_output_limit() {
+        let renderer = Renderer::builder()
+            .max_output(10)
+            .build();
+        for err in render_errors(&renderer, r#"(div "0123456789")"#, &looping_context()) {
+            assert!(matches!(err.root(), RenderError::OutputLimit(10)), "{:?}", err);
+        }
+    }
+
+    #[test]
+    fn test_fuel_limit() {
+        let renderer = Renderer::builder()
+            .fuel(50)
+            .build();
+        for err in render_errors(&renderer, "(for i in $items (for j in $items (b $i $j)))", &looping_context()) {
+            assert!(matches!(err.root(), RenderError::FuelExhausted(50)), "{:?}", err);
+        }
+    }
+
+    #[test]
+    fn test_fuel_limit_in_long_range() {
+        // every iteration spends fuel and the range is not made up front, so a loop over a
+        // huge range stops as soon as the fuel runs out
+        let renderer = Renderer::builder()
+            .fuel(1000)
+            .build();
+        for expr in ["(for i in (range 0 1000000000))", "(div (for (enumerate n i) in (range 0 1000000000 3) (b)))"] {
+            for err in render_errors(&renderer, expr, &looping_context()) {
+                assert!(matches!(err.root(), RenderError::FuelExhausted(1000)), "{}: {:?}", expr, err);
+            }
+        }
+    }
+
+    #[test]
+    fn test_fuel_limit_in_operands() {
+        // running out inside a math or range operand is reported as such
+        let renderer = Renderer::builder()
+            .fuel(5)
+            .build();
+        for expr in ["(div (+ (+ (+ (+ 1 2) 3) 4) 5))", "(for i in (range 0 3 (+ (+ (+ (+ 1 2) 3) 4) 5)) $i)"] {
+            for err in render_errors(&renderer, expr, &looping_context()) {
+                assert!(matches!(err.root(), RenderError::FuelExhausted(5)), "{}: {:?}", expr, err);
+            }
+        }
+    }
+
+    #[test]
+    fn test_limits_not_reached() {
+        // limits that are not reached change nothing, and each render gets a fresh budget
+        let renderer = Renderer::builder()
+            .max_depth(5)
+            .max_iterations(5)
+            .max_output(59)
+            .fuel(30)
+            .build();
+        let template = Template::from_str("(ul (for i in (range 0 10 2) (li $i)))").unwrap();
+        for _ in 0..3 {
+            assert_eq!(renderer.render(&template, &looping_context()).unwrap(), "<ul><li>0</li><li>2</li><li>4</li><li>6</li><li>8</li></ul>");
+        }
+    }
+
+    fn sandboxed() -> Renderer {
+        Renderer::builder()
+            .sandbox(Sandbox::user_content())
+            .build()
+    }
+
+    #[test]
+    fn test_sandbox_rejects_tags() {
+        for (expr, tag) in [(r#"(script "alert(1)")"#, "script"), (r#"(div (iframe (@ (src "/"))))"#, "iframe")] {
+            for err in render_errors(&sandboxed(), expr, &RenderContext::default()) {
+                assert!(matches!(err.root(), RenderError::TagNotAllowed(t) if t == tag), "{}: {:?}", expr, err);
+            }
+        }
+    }
+
+    #[test]
+    fn test_sandbox_rejects_attributes() {
+        for (expr, tag, attr) in [(r#"(p (@ (onclick "alert(1)")) "hi")"#, "p", "onclick"), (r#"(img (@ (style "x")))"#, "img", "style")] {
+            for err in render_errors(&sandboxed(), expr, &RenderContext::default()) {
+                assert!(matches!(err.root(), RenderError::AttributeNotAllowed(t, a) if t == tag && a == attr), "{}: {:?}", expr, err);
+            }
+        }
+    }
+
+    #[test]
+    fn test_sandbox_rejects_script_urls() {
+        let context = RenderContext::builder()
+            .insert("url", "java\tscript:alert(1)")
+            .build();
+        let cases = [
+            (r#"(a (@ (href "JavaScript:alert(1)")) "hi")"#, "JavaScript:alert(1)"),
+            (r#"(a (@ (href $url)) "hi")"#, "java\tscript:alert(1)"),
+            (r#"(a (@ (href "data:text/html,hi")) "hi")"#, "data:text/html,hi"),
+        ];
+        for (expr, url) in cases {
+            for err in render_errors(&sandboxed(), expr, &context) {
+                assert!(matches!(err.root(), RenderError::UrlNotAllowed(t, a, u) if t == "a" && a == "href" && u == url), "{}: {:?}", expr, err);
+            }
+        }
+    }
+
+    #[test]
+    fn test_sandbox_rejects_functions() {
+        for (expr, function) in [(r#"(raw "<script></script>")"#, "raw"), (r#"(include "page")"#, "include"), ("(html (body))", "html")] {
+            for err in render_errors(&sandboxed(), expr, &RenderContext::default()) {
+                assert!(matches!(err.root(), RenderError::FunctionNotAllowed(f) if f == function), "{}: {:?}", expr, err);
+            }
+        }
+    }
+
+    #[test]
+    fn test_sandbox_rejects_operands() {
+        for err in render_errors(&sandboxed(), "(div (+ 1 (script)))", &RenderContext::default()) {
+            assert!(matches!(err.root(), RenderError::TagNotAllowed(t) if t == "script"), "{:?}", err);
+        }
+    }
+
+    #[test]
+    fn test_sandbox_user_content() {
+        let renderer = sandboxed();
+        let context = RenderContext::builder()
+            .insert("items", vec![1, 2])
+            .build();
+        let template = Template::from_str(r#"(div (@ (class "post"))
+                                                (defcomponent item (n) (li (em $n)))
+                                                (ul (for i in $items (item (@ (n $i)))))
+                                                (img (@ (src "data:image/png;base64,AAAA") (alt "x")))
+                                                (a (@ (href "/about")) "about"))"#).unwrap();
+        let expected = r#"<div class="post"><ul><li><em>1</em></li><li><em>2</em></li></ul><img src="data:image/png;base64,AAAA" alt="x"><a href="/about">about</a></div>"#;
+        assert_eq!(renderer.render(&template, &context).unwrap(), expected);
+        assert_eq!(renderer.render_compiled(&renderer.compile(&template), &context).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_sandbox_custom_functions() {
+        // custom functions and tags have to be allowed by name
+        let renderer = Renderer::builder()
+            .function("shout", Box::new(|_, _, _, _| Ok(RenderValue::String("HI".into()))))
+            .sandbox(Sandbox::new().allow_tags(["SECTION"]))
+            .build();
+        for err in render_errors(&renderer, "(section (shout))", &RenderContext::default()) {
+            assert!(matches!(err.root(), RenderError::FunctionNotAllowed(f) if f == "shout"), "{:?}", err);
+        }
+        let renderer = Renderer::builder()
+            .function("shout", Box::new(|_, _, _, _| Ok(RenderValue::String("HI".into()))))
+            .sandbox(Sandbox::new().allow_tags(["SECTION"]).allow_functions(["shout"]))
+            .build();
+        let template = Template::from_str("(section (shout))").unwrap();
+        assert_eq!(renderer.render(&template, &RenderContext::default()).unwrap(), "<section>HI</section>");
+    }
+
+    #[test]
+    fn test_builder_function_registration() {
+        let context = RenderContext::builder()
+            .insert("a", vec![1, 2])
+            .build();
+        let template = Template::from_str("(div (get $a 0) (if true yes))").unwrap();
+
+        let renderer = RendererBuilder::empty().build();
+        assert_eq!(renderer.render(&template, &context).unwrap(), "<div><get>120</get><if>trueyes</if></div>");
+
+        let renderer = Renderer::builder()
+            .without("if")
+            .without("html")
+            .build();
+        assert_eq!(renderer.render(&template, &context).unwrap(), "<div>1<if>trueyes</if></div>");
+        assert_eq!(renderer.render(&Template::from_str("(html)").unwrap(), &context).unwrap(), "<html></html>");
+
+        let result = Renderer::builder()
+            .function("get", Box::new(|_, _, _, _| Ok(RenderValue::Empty)))
+            .try_build();
+        assert!(matches!(result, Err(BuildError::AlreadyRegistered(name)) if name == "get"));
+        let result = Renderer::builder()
+            .function("shout", Box::new(|_, _, _, _| Ok(RenderValue::Empty)))
+            .function("shout", Box::new(|_, _, _, _| Ok(RenderValue::Empty)))
+            .try_build();
+        assert!(matches!(result, Err(BuildError::AlreadyRegistered(name)) if name == "shout"));
+
+        let renderer = Renderer::builder()
+            .replace("get", Box::new(|_, _, _, _| Ok(RenderValue::String("got".into()))))
+            .without("if")
+            .function("if", Box::new(|_, _, _, _| Ok(RenderValue::String("if".into()))))
+            .build();
+        assert_eq!(renderer.render(&template, &context).unwrap(), "<div>gotif</div>");
+
+        let renderer = Renderer::builder()
+            .namespaced_function("ui", "button", Box::new(|_, expr, renderer, context| {
+                Ok(RenderValue::Safe(format!("<button class=\"ui\">{}</button>", renderer.evaluate_multiple(expr, context)?.into_text())))
+            }))
+            .namespaced_function("ui", "get", Box::new(|_, _, _, _| Ok(RenderValue::String("ui".into()))))
+            .build();
+        let template = Template::from_str("(div (ui:button (get $a 1)) (ui:get))").unwrap();
+        assert_eq!(renderer.render(&template, &context).unwrap(), "<div><button class=\"ui\">2</button>ui</div>");
+    }
+
+
+    #[test]
+    fn test_builder_last_function_wins() {
+        let renderer = Renderer::builder()
+            .function("html", Box::new(|_, _, _, _| Ok(RenderValue::String("first".into()))))
+            .function("html", Box::new(|_, _, _, _| Ok(RenderValue::String("last".into()))))
+            .function("shout", Box::new(|_, _, _, _| Ok(RenderValue::String("first".into()))))
+            .function("shout", Box::new(|_, _, _, _| Ok(RenderValue::String("last".into()))))
+            .build();
+        let template = Template::from_str("(div (html) (shout))").unwrap();
+        assert_eq!(renderer.render(&template, &RenderContext::default()).unwrap(), "<div>lastlast</div>");
+        assert_eq!(renderer.render_compiled(&renderer.compile(&template), &RenderContext::default()).unwrap(), "<div>lastlast</div>");
+    }
+
+    #[test]
+    fn test_plugins() {
+        struct Ui {
+            class: String,
+        }
+
+        impl SatoPlugin for Ui {
+            fn name(&self) -> &str {
+                "ui"
+            }
+
+            fn functions(&self) -> Vec<(String, Box<NodeHandler>)> {
+                let class = self.class.clone();
+                vec![("ui:button".into(), Box::new(move |_, expr, renderer, context| {
+                    let label = renderer.evaluate_multiple(expr, context)?.into_text();
+                    Ok(RenderValue::Safe(format!("<button class=\"{}\">{}</button>", class, label)))
+                }))]
+            }
+
+            fn context(&self) -> RenderContext {
+                RenderContext::builder()
+                    .insert("site", "example")
+                    .insert("user", "nobody")
+                    .build()
+            }
+
+            fn templates(&self) -> Vec<(String, Template)> {
+                vec![("ui/footer".into(), Template::from_str(r#"(footer $site " " $user)"#).unwrap())]
+            }
+        }
+
+        let renderer = Renderer::builder()
+            .plugin(Ui { class: "big".into() })
+            .build();
+        let template = Template::from_str(r#"(div (ui:button (if true "ok")) (include "ui/footer"))"#).unwrap();
+        let expected = r#"<div><button class="big">ok</button><footer>example jake</footer></div>"#;
+        let context = RenderContext::builder()
+            .insert("user", "jake")
+            .build();
+        assert_eq!(renderer.render(&template, &context).unwrap(), expected);
+        assert_eq!(renderer.render_compiled(&renderer.compile(&template), &context).unwrap(), expected);
+        assert_eq!(renderer.render(&template, &RenderContext::default()).unwrap(),
+                   r#"<div><button class="big">ok</button><footer>example nobody</footer></div>"#);
+
+        // the builtins are a plugin of their own
+        let renderer = RendererBuilder::empty()
+            .plugin(CorePlugin)
+            .plugin(Ui { class: "small".into() })
+            .build();
+        assert_eq!(renderer.render(&template, &context).unwrap(), r#"<div><button class="small">ok</button><footer>example jake</footer></div>"#);
+        let renderer = RendererBuilder::empty()
+            .plugin(Ui { class: "small".into() })
+            .build();
+        assert_eq!(renderer.render(&template, &context).unwrap(), r#"<div><button class="small"><if>trueok</if></button><include>ui/footer</include></div>"#);
+
+        let result = Renderer::builder()
+            .plugin(Ui { class: "big".into() })
+            .plugin(Ui { class: "small".into() })
+            .try_build();
+        assert!(matches!(result, Err(BuildError::DuplicatePlugin(name)) if name == "ui"));
+        let renderer = Renderer::builder()
+            .plugin(Ui { class: "big".into() })
+            .plugin(Ui { class: "small".into() })
+            .build();
+        assert_eq!(renderer.render(&template, &context).unwrap(), r#"<div><button class="small">ok</button><footer>example jake</footer></div>"#);
+        let result = Renderer::builder()
+            .plugin(CorePlugin)
+            .try_build();
+        assert!(matches!(result, Err(BuildError::DuplicatePlugin(name)) if name == "core"));
+        let result = Renderer::builder()
+            .function("ui:button", Box::new(|_, _, _, _| Ok(RenderValue::Empty)))
+            .plugin(Ui { class: "big".into() })
+            .try_build();
+        assert!(matches!(result, Err(BuildError::AlreadyRegistered(name)) if name == "ui:button"));
+    }
+
+
+    #[test]
+    fn test_plugin_overrides() {
+        struct Theme(&'static str, &'static str);
+
+        impl SatoPlugin for Theme {
+            fn name(&self) -> &str {
+                self.0
+            }
+
+            fn functions(&self) -> Vec<(String, Box<NodeHandler>)> {
+                Vec::new()
+            }
+
+            fn context(&self) -> RenderContext {
+                RenderContext::builder()
+                    .insert("color", self.1)
+                    .build()
+            }
+
+            fn templates(&self) -> Vec<(String, Template)> {
+                vec![("footer".into(), Template::from_str(&format!(r#"(footer "{}" $color)"#, self.0)).unwrap())]
+            }
+        }
+
+        let template = Template::from_str(r#"(include "footer")"#).unwrap();
+        let renderer = Renderer::builder()
+            .plugin(Theme("light", "white"))
+            .plugin(Theme("dark", "black"))
+            .build();
+        assert_eq!(renderer.render(&template, &RenderContext::default()).unwrap(), "<footer>darkblack</footer>");
+
+        let result = Renderer::builder()
+            .plugin(Theme("light", "white"))
+            .plugin(Theme("dark", "black"))
+            .try_build();
+        assert!(matches!(result, Err(BuildError::TemplateAlreadyRegistered(name)) if name == "footer"));
+        let result = Renderer::builder()
+            .template("footer", template.clone())
+            .plugin(Theme("dark", "black"))
+            .try_build();
+        assert!(matches!(result, Err(BuildError::TemplateAlreadyRegistered(name)) if name == "footer"));
+    }
+
+
+    #[test]
+    fn test_nested_object_variable() {
+        let context = RenderContext::builder()
+            .insert("post", RenderContext::builder()
+                    .insert("author", RenderContext::builder().insert("name", "me").build())
+                    .build())
+            .build();
+        for renderer in [Renderer::default(), Renderer::builder().strict(true).build()] {
+            for (expr, expected) in [
+                ("(let ((author $post.author)) (span $author.name))", "<span>me</span>"),
+                ("(for k v in $post.author (span $k $v))", "<span>nameme</span>"),
+                ("(div $post.author)", "<div>me</div>"),
+            ] {
+                let template = Template::from_str(expr).unwrap();
+                assert_eq!(renderer.render(&template, &context).unwrap(), expected, "{}", expr);
+                assert_eq!(renderer.render_compiled(&renderer.compile(&template), &context).unwrap(), expected, "{}", expr);
+            }
+        }
+    }
+
+
+    #[test]
+    fn test_unnamed_list() {
+        let renderer = Renderer::default();
+        for expr in ["(div ((div) x))", "(div (if true ((b) c)))", "(ul (for i in $items ((li) $i)))"] {
+            for err in render_errors(&renderer, expr, &looping_context()) {
+                assert!(matches!(err.root(), RenderError::UnnamedList), "{}: {:?}", expr, err);
+            }
+        }
+        let template = Template::from_str("(div () (let ((a 1)) $a) (cond ((eq 1 1) yes)))").unwrap();
+        assert_eq!(renderer.render(&template, &RenderContext::default()).unwrap(), "<div>1yes</div>");
+        assert_eq!(renderer.render_compiled(&renderer.compile(&template), &RenderContext::default()).unwrap(), "<div>1yes</div>");
     }
 }
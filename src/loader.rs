@@ -0,0 +1,60 @@
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+
+use crate::template::{Template, TemplateError};
+
+
+/// finds templates by name for `include` and `extends`
+pub trait TemplateLoader: Send + Sync {
+    fn load(&self, name: &str) -> Result<Template, TemplateError>;
+}
+
+impl TemplateLoader for HashMap<String, Template> {
+    fn load(&self, name: &str) -> Result<Template, TemplateError> {
+        self.get(name)
+            .cloned()
+            .ok_or_else(|| TemplateError::NotFound(name.into()))
+    }
+}
+
+
+/// loads templates from files under a root directory, names that would leave it are rejected
+pub struct FileSystemLoader {
+    root: PathBuf,
+}
+
+impl FileSystemLoader {
+    pub fn new<P: AsRef<Path>>(root: P) -> FileSystemLoader {
+        FileSystemLoader {
+            root: root.as_ref().to_path_buf(),
+        }
+    }
+
+    fn resolve(&self, name: &str) -> Result<PathBuf, TemplateError> {
+        let relative = Path::new(name);
+        if !relative.components().all(|c| matches!(c, Component::Normal(_) | Component::CurDir)) {
+            return Err(TemplateError::InvalidPath(name.into()))
+        }
+
+        let path = self.root.join(relative);
+        if !path.is_file() {
+            return Err(TemplateError::NotFound(name.into()))
+        }
+
+        // a symlink could still point somewhere else
+        let root = self.root.canonicalize().map_err(|_| TemplateError::NotFound(name.into()))?;
+        let path = path.canonicalize().map_err(|_| TemplateError::NotFound(name.into()))?;
+        if !path.starts_with(root) {
+            return Err(TemplateError::InvalidPath(name.into()))
+        }
+        Ok(path)
+    }
+}
+
+impl TemplateLoader for FileSystemLoader {
+    fn load(&self, name: &str) -> Result<Template, TemplateError> {
+        let path = self.resolve(name)?;
+        let template = std::fs::read_to_string(path).map_err(|_| TemplateError::InvalidFile)?;
+        Template::from_named_str(name, &template)
+    }
+}
@@ -0,0 +1,82 @@
+use crate::builtins;
+use crate::context::RenderContext;
+use crate::renderer::NodeHandler;
+use crate::template::Template;
+
+
+/// a set of functions, templates and context defaults that `RendererBuilder::plugin` adds to
+/// a renderer in one go, for sharing them between projects. functions meant for other
+/// projects are best named under a namespace like `ui:button` so they can not collide.
+pub trait SatoPlugin {
+    /// identifies the plugin, a renderer only takes one plugin with each name
+    fn name(&self) -> &str;
+
+    fn functions(&self) -> Vec<(String, Box<NodeHandler>)>;
+
+    /// variables every render starts with. the context passed to the render takes precedence,
+    /// and when plugins set the same variable the one added last wins
+    fn context(&self) -> RenderContext {
+        RenderContext::default()
+    }
+
+    /// named templates for `include` and `extends`
+    fn templates(&self) -> Vec<(String, Template)> {
+        Vec::new()
+    }
+}
+
+
+/// the builtin functions every `Renderer::builder` starts with
+pub struct CorePlugin;
+
+impl SatoPlugin for CorePlugin {
+    fn name(&self) -> &str {
+        "core"
+    }
+
+    fn functions(&self) -> Vec<(String, Box<NodeHandler>)> {
+        vec![
+            ("html".into(), Box::new(builtins::do_html) as Box<NodeHandler>),
+            ("is-set".into(), Box::new(builtins::do_is_set)),
+            ("default".into(), Box::new(builtins::do_default)),
+            ("null?".into(), Box::new(builtins::do_is_null)),
+            ("is-null".into(), Box::new(builtins::do_is_null)),
+            ("if".into(), Box::new(builtins::do_if)),
+            ("cond".into(), Box::new(builtins::do_cond)),
+            ("when".into(), Box::new(|a,e,r,c| builtins::do_when(a,e,r,c, true))),
+            ("unless".into(), Box::new(|a,e,r,c| builtins::do_when(a,e,r,c, false))),
+            ("switch".into(), Box::new(builtins::do_switch)),
+            ("case".into(), Box::new(builtins::do_case)),
+            ("for".into(), Box::new(builtins::do_for)),
+            ("get".into(), Box::new(builtins::do_get)),
+            ("and".into(), Box::new(builtins::do_and)),
+            ("or".into(), Box::new(builtins::do_or)),
+            ("not".into(), Box::new(builtins::do_not)),
+            ("raw".into(), Box::new(builtins::do_raw)),
+            ("document".into(), Box::new(builtins::do_document)),
+            ("extends".into(), Box::new(builtins::do_extends)),
+            ("block".into(), Box::new(builtins::do_block)),
+            ("super".into(), Box::new(builtins::do_super)),
+            ("include".into(), Box::new(builtins::do_include)),
+            ("defcomponent".into(), Box::new(builtins::do_defcomponent)),
+            ("import".into(), Box::new(builtins::do_import)),
+            ("children".into(), Box::new(builtins::do_children)),
+            ("let".into(), Box::new(builtins::do_let)),
+            ("with".into(), Box::new(builtins::do_let)),
+
+            ("eq".into(), Box::new(|a,e,r,c| builtins::do_cmp_op(a,e,r,c, |q, w| q == w))),
+            ("lt".into(), Box::new(|a,e,r,c| builtins::do_cmp_op(a,e,r,c, |q, w| q < w))),
+            ("gt".into(), Box::new(|a,e,r,c| builtins::do_cmp_op(a,e,r,c, |q, w| q > w))),
+            ("lte".into(), Box::new(|a,e,r,c| builtins::do_cmp_op(a,e,r,c, |q, w| q <= w))),
+            ("gte".into(), Box::new(|a,e,r,c| builtins::do_cmp_op(a,e,r,c, |q, w| q >= w))),
+            ("ne".into(), Box::new(|a,e,r,c| builtins::do_cmp_op(a,e,r,c, |q, w| q != w))),
+
+            ("+".into(), Box::new(|a,e,r,c| builtins::do_math_op(a,e,r,c, |q, w| q.checked_add(w), |q, w| q + w))),
+            ("-".into(), Box::new(|a,e,r,c| builtins::do_math_op(a,e,r,c, |q, w| q.checked_sub(w), |q, w| q - w))),
+            ("*".into(), Box::new(|a,e,r,c| builtins::do_math_op(a,e,r,c, |q, w| q.checked_mul(w), |q, w| q * w))),
+            ("/".into(), Box::new(|a,e,r,c| builtins::do_math_op(a,e,r,c, |q, w| q.checked_div(w), |q, w| q / w))),
+            ("%".into(), Box::new(|a,e,r,c| builtins::do_math_op(a,e,r,c, |q, w| q.checked_rem(w), |q, w| q % w))),
+            ("format-number".into(), Box::new(builtins::do_format_number)),
+        ]
+    }
+}
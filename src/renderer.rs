@@ -1,39 +1,107 @@
+use std::borrow::Cow;
+use std::cell::Cell;
 use std::collections::HashMap;
 use std::convert::{From, Into};
+use std::fmt;
+use std::io;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
 
 use crate::context::{ContextValue, RenderContext};
-use crate::template::{Template, TemplateExprNode, TemplateAttribute};
+use crate::template::{Template, TemplateExprNode, TemplateAttribute, TemplateError, Span};
+use crate::loader::TemplateLoader;
+use crate::plugin::{CorePlugin, SatoPlugin};
+use crate::sandbox::Sandbox;
 use crate::builtins;
+use crate::compiled::{self, CompiledTemplate};
+use crate::escape::{escape_text, escape_attribute};
 
-type NodeHandler = dyn for<'a> Fn(Attributes, &[TemplateExprNode], &'a Renderer, &'a RenderContext) -> Result<RenderValue, RenderError> + Send + Sync;
+pub type NodeHandler = dyn for<'a> Fn(Attributes, &[TemplateExprNode], &'a Renderer, &'a RenderContext) -> Result<RenderValue, RenderError> + Send + Sync;
+// builtins that can write their output as they go rather than returning it
+type StreamHandler = fn(Attributes, &[TemplateExprNode], &Renderer, &RenderContext, &mut dyn fmt::Write) -> Result<(), RenderError>;
 
 #[derive(Debug, Clone)]
 pub enum RenderValue {
     String(String),
+    /// markup that has already been rendered or is otherwise trusted, never escaped
+    Safe(String),
     Integer(i64),
+    Float(f64),
     Boolean(bool),
     Vec(Vec<RenderValue>),
     Object(HashMap<String, RenderValue>),
     Template(Template),
+    Null,
     Empty,
 }
 
 impl RenderValue {
     pub fn finalize(self) -> String {
+        let mut out = String::new();
+        // writing to a string never fails
+        let _ = self.write_to(&mut out);
+        out
+    }
+
+    /// writes what `finalize` would return without joining it into one string first
+    pub fn write_to<W: fmt::Write + ?Sized>(&self, out: &mut W) -> fmt::Result {
+        match self {
+            RenderValue::String(s) => out.write_str(&escape_text(s)),
+            RenderValue::Safe(s) => out.write_str(s),
+            RenderValue::Integer(i) => write!(out, "{}", i),
+            RenderValue::Float(f) => write!(out, "{}", f),
+            RenderValue::Boolean(b) => write!(out, "{}", b),
+            RenderValue::Vec(v) => v.iter().try_for_each(|e| e.write_to(out)),
+            RenderValue::Object(o) => o.values().try_for_each(|v| v.write_to(out)),
+            RenderValue::Template(_t) => Ok(()),
+            RenderValue::Null => Ok(()),
+            RenderValue::Empty => Ok(()),
+        }
+    }
+
+    /// like `finalize` but without escaping, for when the value is not going to be html text
+    pub fn into_text(self) -> String {
         match self {
             RenderValue::String(s) => s,
+            RenderValue::Safe(s) => s,
             RenderValue::Integer(i) => i.to_string(),
+            RenderValue::Float(f) => f.to_string(),
             RenderValue::Boolean(b) => b.to_string(),
-            RenderValue::Vec(v) => v.into_iter().map(|e| e.finalize()).collect::<Vec<_>>().join(""),
-            RenderValue::Object(o) => o.into_iter().map(|(_k, v)| v.finalize()).collect::<Vec<_>>().join(""),
+            RenderValue::Vec(v) => v.into_iter().map(|e| e.into_text()).collect::<Vec<_>>().join(""),
+            RenderValue::Object(o) => o.into_values().map(|v| v.into_text()).collect::<Vec<_>>().join(""),
             RenderValue::Template(_t) => "".into(),
+            RenderValue::Null => "".into(),
             RenderValue::Empty => "".into(),
         }
     }
 
+    /// whether the value counts as true in conditionals like `if` and `and`:
+    ///
+    /// - `Boolean`: its value
+    /// - `String` and `Safe`: true unless empty
+    /// - `Integer` and `Float`: true unless zero (or NaN)
+    /// - `Vec` and `Object`: true unless they have no elements
+    /// - `Template`: always true
+    /// - `Null` and `Empty`: always false
+    pub fn is_truthy(&self) -> bool {
+        match self {
+            RenderValue::Boolean(b) => *b,
+            RenderValue::String(s) => !s.is_empty(),
+            RenderValue::Safe(s) => !s.is_empty(),
+            RenderValue::Integer(i) => *i != 0,
+            RenderValue::Float(f) => *f != 0.0 && !f.is_nan(),
+            RenderValue::Vec(v) => !v.is_empty(),
+            RenderValue::Object(o) => !o.is_empty(),
+            RenderValue::Template(_) => true,
+            RenderValue::Null => false,
+            RenderValue::Empty => false,
+        }
+    }
+
     pub fn as_string(&self) -> Option<&String> {
         match self {
             RenderValue::String(s) => Some(s),
+            RenderValue::Safe(s) => Some(s),
             _ => None
         }
     }
@@ -45,14 +113,26 @@ impl RenderValue {
         }
     }
 
+    /// integers are converted to floats
+    pub fn as_float(&self) -> Option<f64> {
+        match self {
+            RenderValue::Integer(i) => Some(*i as f64),
+            RenderValue::Float(f) => Some(*f),
+            _ => None
+        }
+    }
+
     pub fn join(&self) -> RenderValue {
         match self {
             RenderValue::String(_) => self.clone(),
+            RenderValue::Safe(_) => self.clone(),
             RenderValue::Integer(_) => self.clone(),
+            RenderValue::Float(_) => self.clone(),
             RenderValue::Boolean(_) => self.clone(),
-            RenderValue::Vec(v) => RenderValue::String(v.iter().map(|e| e.clone().finalize()).collect::<Vec<_>>().join("")),
-            RenderValue::Object(o) => RenderValue::String(o.iter().map(|(_k, v)| v.clone().finalize()).collect::<Vec<_>>().join("")),
+            RenderValue::Vec(v) => RenderValue::Safe(v.iter().map(|e| e.clone().finalize()).collect::<Vec<_>>().join("")),
+            RenderValue::Object(o) => RenderValue::Safe(o.values().map(|v| v.clone().finalize()).collect::<Vec<_>>().join("")),
             RenderValue::Template(_) => self.clone(),
+            RenderValue::Null => self.clone(),
             RenderValue::Empty => self.clone(),
         }
     }
@@ -82,6 +162,21 @@ impl From<i64> for RenderValue {
     }
 }
 
+impl From<f64> for RenderValue {
+    fn from(other: f64) -> Self {
+        RenderValue::Float(other)
+    }
+}
+
+impl<T: Into<RenderValue>> From<Option<T>> for RenderValue {
+    fn from(other: Option<T>) -> Self {
+        match other {
+            Some(value) => value.into(),
+            None => RenderValue::Null,
+        }
+    }
+}
+
 impl From<bool> for RenderValue {
     fn from(other: bool) -> Self {
         RenderValue::Boolean(other)
@@ -92,15 +187,18 @@ impl From<&ContextValue> for RenderValue {
     fn from(other: &ContextValue) -> Self {
         match other {
             ContextValue::Integer(i) => RenderValue::Integer(*i),
+            ContextValue::Float(f) => RenderValue::Float(*f),
             ContextValue::Boolean(b) => RenderValue::Boolean(*b),
             ContextValue::String(s) => RenderValue::String(s.clone()),
-            ContextValue::Vec(v) => RenderValue::Vec(v.iter().map(|e| RenderValue::from(e)).collect::<Vec<_>>()),
+            ContextValue::Safe(s) => RenderValue::Safe(s.clone()),
+            ContextValue::Vec(v) => RenderValue::Vec(v.iter().map(RenderValue::from).collect::<Vec<_>>()),
             ContextValue::Object(o) => {
                 RenderValue::Object(o.0.iter()
                                        .map(|(k, v)| (k.clone(), RenderValue::from(v)))
                                        .collect::<HashMap<String, RenderValue>>())
             },
             ContextValue::Template(t) => RenderValue::Template(t.clone()),
+            ContextValue::Null => RenderValue::Null,
         }
     }
 }
@@ -109,9 +207,14 @@ impl PartialEq for RenderValue {
     fn eq(&self, other: &RenderValue) -> bool {
         match (self, other) {
             (RenderValue::Integer(a), RenderValue::Integer(b)) => a == b,
+            (RenderValue::Float(a), RenderValue::Float(b)) => a == b,
+            (RenderValue::Integer(a), RenderValue::Float(b)) => (*a as f64) == *b,
+            (RenderValue::Float(a), RenderValue::Integer(b)) => *a == (*b as f64),
             (RenderValue::Boolean(a), RenderValue::Boolean(b)) => a == b,
             (RenderValue::String(a), RenderValue::String(b)) => a == b,
+            (RenderValue::Safe(a), RenderValue::Safe(b)) => a == b,
             (RenderValue::Vec(a), RenderValue::Vec(b)) => a == b,
+            (RenderValue::Null, RenderValue::Null) => true,
             _ => false,
         }
     }
@@ -121,8 +224,12 @@ impl PartialOrd for RenderValue {
     fn partial_cmp(&self, other: &RenderValue) -> Option<std::cmp::Ordering> {
         match (self, other) {
             (RenderValue::Integer(a), RenderValue::Integer(b)) => a.partial_cmp(b),
+            (RenderValue::Float(a), RenderValue::Float(b)) => a.partial_cmp(b),
+            (RenderValue::Integer(a), RenderValue::Float(b)) => (*a as f64).partial_cmp(b),
+            (RenderValue::Float(a), RenderValue::Integer(b)) => a.partial_cmp(&(*b as f64)),
             (RenderValue::Boolean(a), RenderValue::Boolean(b)) => a.partial_cmp(b),
             (RenderValue::String(a), RenderValue::String(b)) => a.partial_cmp(b),
+            (RenderValue::Safe(a), RenderValue::Safe(b)) => a.partial_cmp(b),
             (RenderValue::Vec(a), RenderValue::Vec(b)) => a.partial_cmp(b),
             _ => None,
         }
@@ -131,7 +238,8 @@ impl PartialOrd for RenderValue {
 
 
 #[derive(Debug, Clone)]
-pub struct Attribute(pub String, pub String);
+/// an evaluated attribute, a value of `None` is a valueless boolean attribute like `disabled`
+pub struct Attribute(pub String, pub Option<String>);
 
 #[derive(Debug, Clone)]
 pub struct Attributes(Vec<Attribute>);
@@ -150,7 +258,13 @@ impl Attributes {
         self.0
             .iter()
             .find(|a| a.0 == name)
-            .map(|a| &a.1)
+            .and_then(|a| a.1.as_ref())
+    }
+
+    pub fn has(&self, name: &str) -> bool {
+        self.0
+            .iter()
+            .any(|a| a.0 == name)
     }
 }
 
@@ -169,184 +283,723 @@ impl<'a> IntoIterator for &'a Attributes {
 pub enum RenderError {
     #[error("expected a variable, found {0}")]
     ExpectedVariable(String),
-    #[error("error expanding variable: {0} ({1:?})")]
+    #[error("error expanding variable: {0}")]
     ExpandVariable(String, String),
-    #[error("error in `is-set`: {0} ({1:?})")]
-    IsSet(String, Vec<TemplateExprNode>),
-    #[error("error in `eq`: {0} ({1:?})")]
-    Cmp(String, Vec<TemplateExprNode>),
-    #[error("error in `if`: {0} ({1:?})")]
-    If(String, Vec<TemplateExprNode>),
-    #[error("error in `case`: {0} ({1:?})")]
-    Case(String, Vec<TemplateExprNode>),
-    #[error("error in `switch`: {0} ({1:?})")]
-    Switch(String, Vec<TemplateExprNode>),
-    #[error("error in `for`: {0} {1:?} ({2:?})")]
-    For(String, Attributes, Vec<TemplateExprNode>),
-    #[error("error in `get`: {0} {1:?}")]
-    Get(String, Vec<TemplateExprNode>),
-
-    #[error("error in math operator: {0} ({1:?})")]
-    Math(String, Vec<TemplateExprNode>),
-
-    #[error("error in `{0}`: {1} ({2:?})")]
-    UserDefined(String, String, Vec<TemplateExprNode>),
+    #[error("error in `is-set`: {0}")]
+    IsSet(String),
+    #[error("error in `eq`: {0}")]
+    Cmp(String),
+    #[error("error in `if`: {0}")]
+    If(String),
+    #[error("error in `not`: {0}")]
+    Not(String),
+    #[error("error in `cond`: {0}")]
+    Cond(String),
+    #[error("error in `when`: {0}")]
+    When(String),
+    #[error("error in `case`: {0}")]
+    Case(String),
+    #[error("error in `switch`: {0}")]
+    Switch(String),
+    #[error("error in `for`: {0}")]
+    For(String),
+    #[error("error in `get`: {0}")]
+    Get(String),
+    #[error("error in `extends`: {0}")]
+    Extends(String),
+    #[error("error in `include`: {0}")]
+    Include(String),
+    #[error("include cycle: {}", .0.join(" -> "))]
+    IncludeCycle(Vec<String>),
+    #[error("error loading template `{0}`: {1}")]
+    Load(String, TemplateError),
+    #[error("error in `defcomponent`: {0}")]
+    Component(String),
+    #[error("error in `let`: {0}")]
+    Let(String),
+    #[error("error in `default`: {0}")]
+    Default(String),
+    #[error("error in `null?`: {0}")]
+    IsNull(String),
+    #[error("undefined variable `${0}`")]
+    UndefinedVariable(String),
+
+    #[error("error in math operator: {0}")]
+    Math(String),
+    #[error("division by zero")]
+    DivisionByZero,
+    #[error("integer overflow")]
+    Overflow,
+    #[error("range step must be positive, found {0}")]
+    RangeStep(i64),
+
+    #[error("`{0}` is not allowed in the sandbox")]
+    FunctionNotAllowed(String),
+    #[error("tag `{0}` is not allowed in the sandbox")]
+    TagNotAllowed(String),
+    #[error("attribute `{1}` on `{0}` is not allowed in the sandbox")]
+    AttributeNotAllowed(String, String),
+    #[error("url `{2}` in `{1}` on `{0}` is not allowed in the sandbox")]
+    UrlNotAllowed(String, String, String),
+
+    #[error("templates are nested more than {0} levels deep")]
+    DepthLimit(usize),
+    #[error("output is longer than {0} bytes")]
+    OutputLimit(usize),
+    #[error("loop has more than {0} iterations")]
+    IterationLimit(usize),
+    #[error("rendering took more than {0} steps")]
+    FuelExhausted(usize),
+    #[error("error in `format-number`: {0}")]
+    FormatNumber(String),
+
+    #[error("void element `{0}` cannot have children")]
+    VoidElement(String),
+    #[error("contents of `{0}` would close the element early")]
+    RawText(String),
+    #[error("`{0}` is not a valid attribute name")]
+    InvalidAttributeName(String),
+    #[error("list has no tag or function name")]
+    UnnamedList,
+
+    #[error("error in `{0}`: {1}")]
+    UserDefined(String, String),
 
     #[error("error in `eval`: {0}")]
     Evaluate(String),
 
+    #[error("error writing output: {0}")]
+    Io(std::io::Error),
+    #[error("error writing output")]
+    Fmt(#[from] std::fmt::Error),
+
+    /// another error along with where in the template it happened
+    #[error("{error}\n{span}")]
+    At {
+        span: Span,
+        error: Box<RenderError>,
+    },
+}
+
+impl RenderError {
+    /// the underlying error without its location
+    pub fn root(&self) -> &RenderError {
+        match self {
+            RenderError::At { error, .. } => error.root(),
+            _ => self,
+        }
+    }
+
+    /// where in the template the error happened, if known
+    pub fn span(&self) -> Option<&Span> {
+        match self {
+            RenderError::At { span, .. } => Some(span),
+            _ => None,
+        }
+    }
+
+    // errors keep the innermost location they were raised at
+    pub(crate) fn at(self, span: &Span) -> RenderError {
+        match self {
+            RenderError::At { .. } => self,
+            _ if !span.is_known() => self,
+            _ => RenderError::At {
+                span: span.clone(),
+                error: Box::new(self),
+            },
+        }
+    }
+}
+
+
+
+/// the kind of markup a renderer produces
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Dialect {
+    /// `<!DOCTYPE html>`, void elements like `<br>` and bare boolean attributes
+    #[default]
+    Html5,
+    /// html5 serialized as xml: an xml declaration, `<br />` and `disabled="disabled"`
+    Xhtml,
+    /// generic xml without any html element rules, empty elements are always self closing
+    Xml,
+}
+
+impl Dialect {
+    /// the text that starts a document in this dialect
+    pub fn prolog(&self) -> &'static str {
+        match self {
+            Dialect::Html5 => "<!DOCTYPE html>",
+            Dialect::Xhtml => r#"<?xml version="1.0" encoding="UTF-8"?><!DOCTYPE html>"#,
+            Dialect::Xml => r#"<?xml version="1.0" encoding="UTF-8"?>"#,
+        }
+    }
+}
+
+// deep enough for any reasonable template while staying well inside a thread's stack
+const DEFAULT_MAX_DEPTH: usize = 128;
+
+// limits on the work a single render can do, all but depth are unlimited by default
+#[derive(Debug, Clone, Copy)]
+struct Limits {
+    depth: usize,
+    output: Option<usize>,
+    iterations: Option<usize>,
+    fuel: Option<usize>,
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits {
+            depth: DEFAULT_MAX_DEPTH,
+            output: None,
+            iterations: None,
+            fuel: None,
+        }
+    }
+}
+
+// how deep and how long the render running on this thread has gone. renders started from
+// inside another, like templates in the context, count towards the same totals
+#[derive(Clone, Copy)]
+struct Usage {
+    depth: usize,
+    steps: usize,
+}
+
+thread_local! {
+    static USAGE: Cell<Usage> = const { Cell::new(Usage { depth: 0, steps: 0 }) };
+}
+
+// leaves the level of nesting entered by `Renderer::step` when dropped
+pub(crate) struct Nesting;
+
+impl Drop for Nesting {
+    fn drop(&mut self) {
+        USAGE.with(|usage| {
+            let mut current = usage.get();
+            current.depth -= 1;
+            usage.set(current);
+        });
+    }
+}
+
+// stops writing once the output would go over `remaining` bytes
+struct LimitedWriter<'a, W: fmt::Write> {
+    inner: &'a mut W,
+    remaining: usize,
+    exceeded: bool,
+}
+
+impl<W: fmt::Write> fmt::Write for LimitedWriter<'_, W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if s.len() > self.remaining {
+            self.exceeded = true;
+            return Err(fmt::Error)
+        }
+        self.remaining -= s.len();
+        self.inner.write_str(s)
+    }
 }
 
 pub struct Renderer {
-    functions: HashMap<String, Box<NodeHandler>>,
+    // tells compiled templates apart from ones compiled by another renderer
+    id: u64,
+    functions: HashMap<String, Arc<NodeHandler>>,
+    streaming: HashMap<String, StreamHandler>,
+    templates: HashMap<String, Template>,
+    loader: Option<Box<dyn TemplateLoader>>,
+    dialect: Dialect,
+    strict: bool,
+    limits: Limits,
+    sandbox: Option<Sandbox>,
+    defaults: RenderContext,
 }
 
-pub(crate) fn expand_variable(expr: &String, renderer: &Renderer, context: &RenderContext) -> Result<RenderValue, RenderError> {
-    Ok(
-        if expr.starts_with('$') {
-            if expr.contains(".") {
-                expr[1..].split('.').try_fold((context.clone(), None), |(mut context, output), expr| {
-                    if output.is_some() {
-                        return Ok((context, output))
-                    }
+// finds the value at a variable path like `post.author.name`
+pub(crate) fn lookup_variable<'a>(path: &str, context: &'a RenderContext) -> Option<&'a ContextValue> {
+    lookup_path(&path.split('.').collect::<Vec<_>>(), context)
+}
+
+fn lookup_path<'a, S: AsRef<str>>(path: &[S], context: &'a RenderContext) -> Option<&'a ContextValue> {
+    let (first, rest) = path.split_first()?;
+    let mut value = context.get(first.as_ref())?;
+    for key in rest {
+        match value {
+            ContextValue::Object(o) => value = o.get(key.as_ref())?,
+            _ => return None,
+        }
+    }
+    Some(value)
+}
 
-                    match context.get(expr) {
-                        Some(ContextValue::Object(o)) => {
-                            context = o.clone();
-                            Ok((context, output))
-                        },
-                        Some(item) => {
-                            let item = item.into();
-                            Ok((context, Some(item)))
-                        },
-                        None => Ok((context, Some(RenderValue::Boolean(false))))
+pub(crate) fn expand_variable(expr: &str, renderer: &Renderer, context: &RenderContext) -> Result<RenderValue, RenderError> {
+    match expr.strip_prefix('$') {
+        Some(var) => expand_path(expr, &var.split('.').collect::<Vec<_>>(), renderer, context),
+        None => Ok(RenderValue::String(expr.into())),
+    }
+}
+
+// expands the variable `expr` whose name has already been split into `path`
+pub(crate) fn expand_path<S: AsRef<str>>(expr: &str, path: &[S], renderer: &Renderer, context: &RenderContext) -> Result<RenderValue, RenderError> {
+    if renderer.strict && lookup_path(path, context).is_none() {
+        return Err(RenderError::UndefinedVariable(expr.strip_prefix('$').unwrap_or(expr).into()))
+    }
+
+    if let [var] = path {
+        context.get(var.as_ref())
+            .map(RenderValue::from)
+            .map(|e| {
+                match e {
+                    RenderValue::Template(t) => {
+                        Ok(RenderValue::Safe(renderer.render(&t, context)?))
                     }
-                })?
-                    .1
-                    .unwrap_or_else(|| expr.clone().into())
-            }
-            else {
-                context.get(&expr[1..])
-                    .map(|e| e.try_into())
-                    .unwrap_or(Ok(RenderValue::String(expr.clone())))
-                    .map(|e| {
-                        match e {
-                            RenderValue::Vec(v) => {
-                                Ok(RenderValue::Vec(v.iter()
-                                                    .map(|v| {
-                                                        match v {
-                                                            RenderValue::String(s) => expand_variable(s, renderer, context),
-                                                            _ => Ok(v.clone())
-                                                        }
-                                                    })
-                                                    .collect::<Result<Vec<_>, _>>()?))
-                            },
-                            RenderValue::Template(t) => {
-                                Ok(RenderValue::String(renderer.render(&t, context)?))
-                            }
-                            _ => Ok(e)
-                        }
-                    })
-                    .unwrap_or(Ok(RenderValue::String(expr.clone())))?
+                    _ => Ok(e)
+                }
+            })
+            .unwrap_or(Ok(RenderValue::String(expr.into())))
+    }
+    else {
+        let mut object = context;
+        for (i, key) in path.iter().enumerate() {
+            match object.get(key.as_ref()) {
+                Some(ContextValue::Object(o)) if i + 1 < path.len() => object = o,
+                Some(item) => return Ok(item.into()),
+                None => return Ok(RenderValue::Boolean(false)),
             }
         }
-        else {
-            RenderValue::String(expr.clone())
+        Ok(RenderValue::String(expr.into()))
+    }
+}
+
+const VOID_ELEMENTS: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input",
+    "link", "meta", "param", "source", "track", "wbr",
+];
+
+// contents of these are not escaped, so they cannot be allowed to contain their own closing tag
+const RAW_TEXT_ELEMENTS: &[&str] = &["script", "style"];
+
+pub(crate) fn is_void_element(tag: &str) -> bool {
+    VOID_ELEMENTS.iter().any(|t| t.eq_ignore_ascii_case(tag))
+}
+
+pub(crate) fn is_raw_text_element(tag: &str) -> bool {
+    RAW_TEXT_ELEMENTS.iter().any(|t| t.eq_ignore_ascii_case(tag))
+}
+
+// writes the `>` that finishes an open tag only once there is content after it, so an
+// element that turns out to be empty can be self-closed instead
+struct OpenTag<'a> {
+    out: &'a mut dyn fmt::Write,
+    finished: bool,
+}
+
+impl fmt::Write for OpenTag<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if !self.finished && !s.is_empty() {
+            self.out.write_char('>')?;
+            self.finished = true;
         }
-    )
+        self.out.write_str(s)
+    }
 }
 
 pub(crate) fn basic_html_tag(tag: String, attrs: &Attributes, expr: &[TemplateExprNode], renderer: &Renderer, context: &RenderContext) -> Result<RenderValue, RenderError> {
-    let mut l = Vec::<RenderValue>::new();
+    let mut out = String::new();
+    write_html_tag(&tag, attrs, expr, renderer, context, &mut out)?;
+    Ok(RenderValue::Safe(out))
+}
+
+// names that can not end the attribute or the tag they are written in
+fn is_valid_attribute_name(name: &str) -> bool {
+    !name.is_empty() && !name.chars().any(|c| c.is_whitespace() || c.is_control() || matches!(c, '"' | '\'' | '<' | '>' | '/' | '='))
+}
+
+// the text of an attribute inside an open tag, including its leading space
+pub(crate) fn format_attribute(attr: &Attribute, dialect: Dialect) -> String {
+    match (&attr.1, dialect) {
+        (Some(value), _) => format!(" {}=\"{}\"", attr.0, escape_attribute(value)),
+        (None, Dialect::Html5) => format!(" {}", attr.0),
+        (None, _) => format!(" {}=\"{}\"", attr.0, escape_attribute(&attr.0)),
+    }
+}
+
+pub(crate) fn write_html_tag(tag: &str, attrs: &Attributes, expr: &[TemplateExprNode], renderer: &Renderer, context: &RenderContext, out: &mut dyn fmt::Write) -> Result<(), RenderError> {
+    if let Some(sandbox) = renderer.sandbox() {
+        sandbox.check_tag(tag)?;
+        for attr in attrs {
+            sandbox.check_attribute(tag, attr)?;
+        }
+    }
+    let dialect = renderer.dialect();
     let attr_str = attrs.0.iter()
-        .map(|attr| {
-            Ok(format!(" {}=\"{}\"", attr.0, attr.1))
-        })
-        .collect::<Result<Vec<_>, RenderError>>()?
-        .join("");
-    if expr.len() == 0 {
-        l.push(format!("<{}{} />", tag, attr_str).into());
+        .map(|attr| format_attribute(attr, dialect))
+        .collect::<String>();
+    if dialect != Dialect::Xml {
+        if is_void_element(tag) && !expr.is_empty() {
+            return Err(RenderError::VoidElement(tag.into()))
+        }
+        if is_raw_text_element(tag) {
+            let text = renderer.evaluate_multiple(expr, context)?.into_text();
+            if text.to_ascii_lowercase().contains(&format!("</{}", tag.to_ascii_lowercase())) {
+                return Err(RenderError::RawText(tag.into()))
+            }
+            write!(out, "<{}{}>{}</{}>", tag, attr_str, text, tag)?;
+            return Ok(())
+        }
+    }
+    write_element(tag, &attr_str, dialect, out, |out| renderer.emit_multiple(expr, context, out))
+}
+
+// writes an element around whatever `content` writes, which is not called for void elements
+pub(crate) fn write_element<F>(tag: &str, attr_str: &str, dialect: Dialect, out: &mut dyn fmt::Write, content: F) -> Result<(), RenderError>
+where
+    F: FnOnce(&mut dyn fmt::Write) -> Result<(), RenderError>
+{
+    if dialect == Dialect::Xml {
+        write!(out, "<{}{}", tag, attr_str)?;
+        let mut open_tag = OpenTag {
+            out: &mut *out,
+            finished: false,
+        };
+        content(&mut open_tag)?;
+        if open_tag.finished {
+            write!(out, "</{}>", tag)?;
+        }
+        else {
+            out.write_str(" />")?;
+        }
+    }
+    else if is_void_element(tag) {
+        match dialect {
+            Dialect::Xhtml => write!(out, "<{}{} />", tag, attr_str)?,
+            _ => write!(out, "<{}{}>", tag, attr_str)?,
+        }
     }
     else {
-        l.push(format!("<{}{}>", tag, attr_str).into());
-        l.push(renderer.evaluate_multiple(expr, context)?.into());
-        l.push(format!("</{}>", tag).into());
+        write!(out, "<{}{}>", tag, attr_str)?;
+        content(out)?;
+        write!(out, "</{}>", tag)?;
     }
-    Ok(l.into())
+    Ok(())
 }
 
 
-fn standard_issue_functions() -> HashMap<String, Box<NodeHandler>> {
-    let mut functions = HashMap::new();
-    functions.insert("html".into(), Box::new(builtins::do_html) as Box<NodeHandler>);
-    functions.insert("is-set".into(), Box::new(builtins::do_is_set));
-    functions.insert("if".into(), Box::new(builtins::do_if));
-    functions.insert("switch".into(), Box::new(builtins::do_switch));
-    functions.insert("case".into(), Box::new(builtins::do_case));
-    functions.insert("for".into(), Box::new(builtins::do_for));
-    functions.insert("get".into(), Box::new(builtins::do_get));
-
-    functions.insert("eq".into(), Box::new(|a,e,r,c| builtins::do_cmp_op(a,e,r,c, |q, w| q == w)));
-    functions.insert("lt".into(), Box::new(|a,e,r,c| builtins::do_cmp_op(a,e,r,c, |q, w| q < w)));
-    functions.insert("gt".into(), Box::new(|a,e,r,c| builtins::do_cmp_op(a,e,r,c, |q, w| q > w)));
-    functions.insert("lte".into(), Box::new(|a,e,r,c| builtins::do_cmp_op(a,e,r,c, |q, w| q <= w)));
-    functions.insert("gte".into(), Box::new(|a,e,r,c| builtins::do_cmp_op(a,e,r,c, |q, w| q >= w)));
-    functions.insert("ne".into(), Box::new(|a,e,r,c| builtins::do_cmp_op(a,e,r,c, |q, w| q != w)));
+fn standard_issue_streaming() -> HashMap<String, StreamHandler> {
+    let mut streaming = HashMap::new();
+    streaming.insert("html".into(), builtins::write_html as StreamHandler);
+    streaming.insert("document".into(), builtins::write_document);
+    streaming.insert("if".into(), builtins::write_if);
+    streaming.insert("when".into(), |a,e,r,c,o| builtins::write_when(a,e,r,c,o, true));
+    streaming.insert("unless".into(), |a,e,r,c,o| builtins::write_when(a,e,r,c,o, false));
+    streaming.insert("cond".into(), builtins::write_cond);
+    streaming.insert("switch".into(), builtins::write_switch);
+    streaming.insert("case".into(), builtins::write_case);
+    streaming.insert("for".into(), builtins::write_for);
+    streaming.insert("let".into(), builtins::write_let);
+    streaming.insert("with".into(), builtins::write_let);
+    streaming.insert("extends".into(), builtins::write_extends);
+    streaming.insert("block".into(), builtins::write_block);
+    streaming.insert("include".into(), builtins::write_include);
+    streaming
+}
 
-    functions.insert("+".into(), Box::new(|a,e,r,c| builtins::do_math_op(a,e,r,c, |q, w| q + w)));
-    functions.insert("-".into(), Box::new(|a,e,r,c| builtins::do_math_op(a,e,r,c, |q, w| q - w)));
-    functions.insert("*".into(), Box::new(|a,e,r,c| builtins::do_math_op(a,e,r,c, |q, w| q * w)));
-    functions.insert("/".into(), Box::new(|a,e,r,c| builtins::do_math_op(a,e,r,c, |q, w| q / w)));
-    functions.insert("%".into(), Box::new(|a,e,r,c| builtins::do_math_op(a,e,r,c, |q, w| q % w)));
+// lets `render_to_writer` share the `fmt::Write` based rendering, keeping the actual io error
+struct IoWriter<'a, W: io::Write> {
+    inner: &'a mut W,
+    error: Option<io::Error>,
+}
 
-    functions
+impl<W: io::Write> fmt::Write for IoWriter<'_, W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.inner.write_all(s.as_bytes())
+            .map_err(|err| {
+                self.error = Some(err);
+                fmt::Error
+            })
+    }
 }
 
+static NEXT_RENDERER_ID: AtomicU64 = AtomicU64::new(0);
+
 impl Renderer {
     pub fn builder() -> RendererBuilder {
         RendererBuilder::new()
     }
 
+    pub fn dialect(&self) -> Dialect {
+        self.dialect
+    }
+
+    /// whether undefined variables are an error rather than rendered as their name
+    pub fn strict(&self) -> bool {
+        self.strict
+    }
+
+    pub(crate) fn id(&self) -> u64 {
+        self.id
+    }
+
+    // counts a step of evaluation one level deeper than the current one, until the returned
+    // guard is dropped
+    pub(crate) fn step(&self) -> Result<Nesting, RenderError> {
+        let usage = USAGE.with(|usage| {
+            let mut current = usage.get();
+            if current.depth == 0 {
+                current.steps = 0;
+            }
+            current.depth += 1;
+            current.steps += 1;
+            usage.set(current);
+            current
+        });
+        let nesting = Nesting;
+
+        if usage.depth > self.limits.depth {
+            return Err(RenderError::DepthLimit(self.limits.depth))
+        }
+        match self.limits.fuel {
+            Some(fuel) if usage.steps > fuel => Err(RenderError::FuelExhausted(fuel)),
+            _ => Ok(nesting),
+        }
+    }
+
+    pub(crate) fn check_iterations(&self, iterations: usize) -> Result<(), RenderError> {
+        match self.limits.iterations {
+            Some(max) if iterations > max => Err(RenderError::IterationLimit(max)),
+            _ => Ok(()),
+        }
+    }
+
+    fn limit_output<W, F>(&self, out: &mut W, render: F) -> Result<(), RenderError>
+    where
+        W: fmt::Write,
+        F: FnOnce(&mut dyn fmt::Write) -> Result<(), RenderError>
+    {
+        let max = match self.limits.output {
+            Some(max) => max,
+            None => return render(out),
+        };
+        let mut limited = LimitedWriter {
+            inner: out,
+            remaining: max,
+            exceeded: false,
+        };
+        render(&mut limited)
+            .map_err(|err| if limited.exceeded { RenderError::OutputLimit(max) } else { err })
+    }
+
+    pub(crate) fn handler(&self, name: &str) -> Option<&Arc<NodeHandler>> {
+        self.functions.get(name)
+    }
+
+    // builtins that write their own output, only while they have not been replaced
+    pub(crate) fn is_streaming(&self, name: &str) -> bool {
+        self.streaming.contains_key(name)
+    }
+
+    // the context a render starts with, any variables plugins set under the ones passed in
+    fn with_defaults<'c>(&self, context: &'c RenderContext) -> Cow<'c, RenderContext> {
+        if self.defaults.0.is_empty() {
+            return Cow::Borrowed(context)
+        }
+        let mut merged = self.defaults.clone();
+        merged.0.extend(context.0.iter().map(|(key, value)| (key.clone(), value.clone())));
+        Cow::Owned(merged)
+    }
+
+    pub(crate) fn sandbox(&self) -> Option<&Sandbox> {
+        self.sandbox.as_ref()
+    }
+
+    /// looks up a template registered with `RendererBuilder::template`, falling back to the loader
+    pub fn load_template(&self, name: &str) -> Result<Template, RenderError> {
+        match (self.templates.get(name), &self.loader) {
+            (Some(template), _) => Ok(template.clone()),
+            (None, Some(loader)) => loader.load(name),
+            (None, None) => Err(TemplateError::NotFound(name.into())),
+        }
+        .map_err(|err| RenderError::Load(name.into(), err))
+    }
+
     pub fn evaluate_multiple(&self, expr: &[TemplateExprNode], context: &RenderContext) -> Result<RenderValue, RenderError> {
+        // components defined in this list are visible to all of it
+        let _components = builtins::declare_components(expr, self, context)?;
         Ok(expr
-           .into_iter()
-           .map(|e| self.evaluate(&e, context))
+           .iter()
+           .map(|e| self.evaluate(e, context))
            .collect::<Result<Vec<_>, _>>()?
            .into())
     }
 
-    pub fn evaluate_attrs(&self, attrs: &Vec<TemplateAttribute>, context: &RenderContext) -> Result<Attributes, RenderError> {
+    pub fn evaluate_attrs(&self, attrs: &[TemplateAttribute], context: &RenderContext) -> Result<Attributes, RenderError> {
         Ok(Attributes(attrs
                       .iter()
-                      .map(|attr| {
-                          Ok(Attribute(self.evaluate(&attr.0, context)?.finalize(), self.evaluate_multiple(&attr.1, context)?.finalize()))
-                      })
-                      .collect::<Result<Vec<_>, _>>()?))
+                      .map(|attr| self.evaluate_attribute(attr, context))
+                      .collect::<Result<Vec<_>, RenderError>>()?
+                      .into_iter()
+                      .flatten()
+                      .collect()))
+    }
+
+    pub(crate) fn evaluate_attribute(&self, attr: &TemplateAttribute, context: &RenderContext) -> Result<Option<Attribute>, RenderError> {
+        let name = self.evaluate(&attr.0, context)?.into_text();
+        if !is_valid_attribute_name(&name) {
+            return Err(RenderError::InvalidAttributeName(name))
+        }
+        // a lone value that is false, empty or an unset variable omits the attribute, true leaves
+        // it valueless. the symbol `false` is only text, as attributes like `draggable` take it
+        Ok(match attr.1.as_slice() {
+            [] => Some(Attribute(name, None)),
+            [TemplateExprNode::Identifier(ident, _)] if !self.strict && ident.strip_prefix('$').is_some_and(|var| lookup_variable(var, context).is_none()) => None,
+            [value] => match self.evaluate(value, context)? {
+                RenderValue::Boolean(false) | RenderValue::Null | RenderValue::Empty => None,
+                RenderValue::Boolean(true) => Some(Attribute(name, None)),
+                value => Some(Attribute(name, Some(value.into_text()))),
+            },
+            values => Some(Attribute(name, Some(self.evaluate_multiple(values, context)?.into_text()))),
+        })
     }
 
     pub fn evaluate(&self, expr: &TemplateExprNode, context: &RenderContext) -> Result<RenderValue, RenderError> {
+        self.step()
+            .and_then(|_nesting| self.evaluate_node(expr, context))
+            .map_err(|err| err.at(expr.span()))
+    }
+
+    fn evaluate_node(&self, expr: &TemplateExprNode, context: &RenderContext) -> Result<RenderValue, RenderError> {
         Ok(match expr {
-            TemplateExprNode::Identifier(ident) => {
-                expand_variable(&ident, self, context)?
+            TemplateExprNode::Identifier(ident, _) => {
+                expand_variable(ident, self, context)?
+            },
+            TemplateExprNode::String(s, _) => {
+                RenderValue::String(s.clone())
             },
-            TemplateExprNode::Integer(i) => {
+            TemplateExprNode::Integer(i, _) => {
                 (*i).into()
             },
+            TemplateExprNode::Float(f, _) => {
+                (*f).into()
+            },
+            // `()` is nothing, other lists without a name are only read by builtins like `let`
+            TemplateExprNode::Tag(tag) if tag.tag.is_empty() && tag.children.is_empty() => {
+                RenderValue::Empty
+            },
+            TemplateExprNode::Tag(tag) if tag.tag.is_empty() => {
+                return Err(RenderError::UnnamedList)
+            },
             TemplateExprNode::Tag(tag) => {
-                let eval_attrs = self.evaluate_attrs(&tag.attrs, context)?;
-                match self.functions.get(&tag.tag) {
-                    Some(op_func) => op_func(eval_attrs, &tag.children, self, context)?,
-                    None => basic_html_tag(tag.tag.clone(), &eval_attrs, &tag.children, self, context)?,
+                if let Some(op_func) = self.functions.get(&tag.tag) {
+                    if let Some(sandbox) = &self.sandbox {
+                        sandbox.check_function(&tag.tag)?;
+                    }
+                    op_func(self.evaluate_attrs(&tag.attrs, context)?, &tag.children, self, context)?
+                }
+                else if let Some(component) = builtins::find_component(&tag.tag) {
+                    builtins::call_component(&component, tag, self, context)?
+                }
+                else {
+                    basic_html_tag(tag.tag.clone(), &self.evaluate_attrs(&tag.attrs, context)?, &tag.children, self, context)?
                 }
             },
         })
     }
 
+    // like `evaluate` but writes html tags out as their children are evaluated
+    pub(crate) fn emit(&self, expr: &TemplateExprNode, context: &RenderContext, out: &mut dyn fmt::Write) -> Result<(), RenderError> {
+        self.step()
+            .and_then(|_nesting| self.emit_node(expr, context, out))
+            .map_err(|err| err.at(expr.span()))
+    }
+
+    pub(crate) fn emit_multiple(&self, expr: &[TemplateExprNode], context: &RenderContext, out: &mut dyn fmt::Write) -> Result<(), RenderError> {
+        let _components = builtins::declare_components(expr, self, context)?;
+        expr.iter()
+            .try_for_each(|e| self.emit(e, context, out))
+    }
+
+    fn emit_node(&self, expr: &TemplateExprNode, context: &RenderContext, out: &mut dyn fmt::Write) -> Result<(), RenderError> {
+        match expr {
+            TemplateExprNode::Tag(tag) if !tag.tag.is_empty() => {
+                if let Some(write_func) = self.streaming.get(&tag.tag) {
+                    if let Some(sandbox) = &self.sandbox {
+                        sandbox.check_function(&tag.tag)?;
+                    }
+                    write_func(self.evaluate_attrs(&tag.attrs, context)?, &tag.children, self, context, out)
+                }
+                else if self.functions.contains_key(&tag.tag) {
+                    Ok(self.evaluate_node(expr, context)?.write_to(out)?)
+                }
+                else if let Some(component) = builtins::find_component(&tag.tag) {
+                    builtins::write_component(&component, tag, self, context, out)
+                }
+                else {
+                    write_html_tag(&tag.tag, &self.evaluate_attrs(&tag.attrs, context)?, &tag.children, self, context, out)
+                }
+            },
+            _ => Ok(self.evaluate_node(expr, context)?.write_to(out)?),
+        }
+    }
+
     pub fn render(&self, template: &Template, context: &RenderContext) -> Result<String, RenderError> {
-        Ok(self.evaluate(&template.expr, context)?.finalize())
+        let mut out = String::new();
+        self.render_to_fmt(template, context, &mut out)?;
+        Ok(out)
+    }
+
+    /// renders into `out` as the template is evaluated instead of building the whole output first
+    pub fn render_to_fmt<W: fmt::Write>(&self, template: &Template, context: &RenderContext, out: &mut W) -> Result<(), RenderError> {
+        let context = self.with_defaults(context);
+        let _render = builtins::render_scope();
+        self.limit_output(out, |out| self.emit(&template.expr, &context, out))
+    }
+
+    /// like `render_to_fmt` for byte streams such as files and http bodies. output is written in
+    /// many small pieces, so unbuffered writers should be wrapped in a `BufWriter`.
+    pub fn render_to_writer<W: io::Write>(&self, template: &Template, context: &RenderContext, out: &mut W) -> Result<(), RenderError> {
+        write_io(out, |writer| self.render_to_fmt(template, context, writer))
+    }
+
+    /// prepares a template to be rendered many times by this renderer, see `CompiledTemplate`
+    pub fn compile(&self, template: &Template) -> CompiledTemplate {
+        compiled::compile(template, self)
+    }
+
+    pub fn render_compiled(&self, template: &CompiledTemplate, context: &RenderContext) -> Result<String, RenderError> {
+        let mut out = String::new();
+        self.render_compiled_to_fmt(template, context, &mut out)?;
+        Ok(out)
+    }
+
+    /// a template compiled by a different renderer is rendered from its source instead
+    pub fn render_compiled_to_fmt<W: fmt::Write>(&self, template: &CompiledTemplate, context: &RenderContext, out: &mut W) -> Result<(), RenderError> {
+        if template.renderer_id() != self.id {
+            return self.render_to_fmt(template.template(), context, out)
+        }
+        let context = self.with_defaults(context);
+        let _render = builtins::render_scope();
+        self.limit_output(out, |out| compiled::emit(template, self, &context, out))
+    }
+
+    pub fn render_compiled_to_writer<W: io::Write>(&self, template: &CompiledTemplate, context: &RenderContext, out: &mut W) -> Result<(), RenderError> {
+        write_io(out, |writer| self.render_compiled_to_fmt(template, context, writer))
     }
 }
 
+fn write_io<W, F>(out: &mut W, render: F) -> Result<(), RenderError>
+where
+    W: io::Write,
+    F: FnOnce(&mut IoWriter<W>) -> Result<(), RenderError>
+{
+    let mut writer = IoWriter {
+        inner: out,
+        error: None,
+    };
+    render(&mut writer)
+        .map_err(|err| writer.error.take().map(RenderError::Io).unwrap_or(err))
+}
+
 impl Default for Renderer {
     fn default() -> Renderer {
         Renderer::builder().build()
@@ -354,29 +1007,209 @@ impl Default for Renderer {
 }
 
 
+#[derive(thiserror::Error, Debug)]
+pub enum BuildError {
+    #[error("function `{0}` is already registered, use `replace` to override it")]
+    AlreadyRegistered(String),
+    #[error("plugin `{0}` is already installed")]
+    DuplicatePlugin(String),
+    #[error("template `{0}` is already registered")]
+    TemplateAlreadyRegistered(String),
+}
+
 pub struct RendererBuilder {
-    functions: HashMap<String, Box<NodeHandler>>,
+    functions: HashMap<String, Arc<NodeHandler>>,
+    streaming: HashMap<String, StreamHandler>,
+    templates: HashMap<String, Template>,
+    loader: Option<Box<dyn TemplateLoader>>,
+    dialect: Dialect,
+    strict: bool,
+    limits: Limits,
+    sandbox: Option<Sandbox>,
+    defaults: RenderContext,
+    plugins: Vec<String>,
+    errors: Vec<BuildError>,
 }
 
 impl RendererBuilder {
     fn new() -> Self {
         RendererBuilder {
-            functions: standard_issue_functions(),
+            streaming: standard_issue_streaming(),
+            ..RendererBuilder::empty().plugin(CorePlugin)
         }
     }
 
+    /// a builder without any of the builtin functions, every tag is rendered as an element
+    /// unless a function is added for it
+    pub fn empty() -> Self {
+        RendererBuilder {
+            functions: HashMap::new(),
+            streaming: HashMap::new(),
+            templates: HashMap::new(),
+            loader: None,
+            dialect: Dialect::default(),
+            strict: false,
+            limits: Limits::default(),
+            sandbox: None,
+            defaults: RenderContext::default(),
+            plugins: Vec::new(),
+            errors: Vec::new(),
+        }
+    }
+
+    pub fn dialect(mut self, dialect: Dialect) -> Self {
+        self.dialect = dialect;
+        self
+    }
+
+    /// makes referencing an unset variable or object key an `UndefinedVariable` error
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// how deeply tags, components, includes and templates in the context can nest before
+    /// rendering stops with `DepthLimit`, 128 by default
+    pub fn max_depth(mut self, depth: usize) -> Self {
+        self.limits.depth = depth;
+        self
+    }
+
+    /// the most bytes a render can output before stopping with `OutputLimit`
+    pub fn max_output(mut self, bytes: usize) -> Self {
+        self.limits.output = Some(bytes);
+        self
+    }
+
+    /// the most times a single `for` can loop, larger loops are an `IterationLimit` error
+    /// before any of them runs
+    pub fn max_iterations(mut self, iterations: usize) -> Self {
+        self.limits.iterations = Some(iterations);
+        self
+    }
+
+    /// the most expressions a render can evaluate before stopping with `FuelExhausted`
+    pub fn fuel(mut self, steps: usize) -> Self {
+        self.limits.fuel = Some(steps);
+        self
+    }
+
+    /// only lets templates use what the sandbox allows, for rendering templates written by
+    /// people you do not trust
+    pub fn sandbox(mut self, sandbox: Sandbox) -> Self {
+        self.sandbox = Some(sandbox);
+        self
+    }
+
+    /// adds a function for templates to call. a name that is already registered, such as one
+    /// of the builtins, is overridden by the function added last, which `try_build` reports
+    /// as an error, see `replace`.
     pub fn function<S>(mut self, name: S, func: Box<NodeHandler>) -> Self
     where
         S: std::convert::Into<String>
     {
-        self.functions.insert(name.into(), func);
+        let name = name.into();
+        if self.functions.contains_key(&name) {
+            self.errors.push(BuildError::AlreadyRegistered(name.clone()));
+            self = self.without(&name);
+        }
+        self.functions.insert(name, Arc::from(func));
+        self
+    }
+
+    /// adds a function called as `namespace:name`, which keeps functions from different
+    /// libraries from colliding with each other or with the builtins
+    pub fn namespaced_function<N, S>(self, namespace: N, name: S, func: Box<NodeHandler>) -> Self
+    where
+        N: std::convert::AsRef<str>,
+        S: std::convert::AsRef<str>,
+    {
+        self.function(format!("{}:{}", namespace.as_ref(), name.as_ref()), func)
+    }
+
+    /// adds a function whether or not one is already registered with the name, without
+    /// `try_build` failing
+    pub fn replace<S>(self, name: S, func: Box<NodeHandler>) -> Self
+    where
+        S: std::convert::Into<String>
+    {
+        let name = name.into();
+        self.without(&name)
+            .function(name, func)
+    }
+
+    /// removes a function, its tag is then rendered as an element
+    pub fn without(mut self, name: &str) -> Self {
+        // a replaced builtin must not keep being used when streaming
+        self.streaming.remove(name);
+        self.functions.remove(name);
+        self
+    }
+
+    /// adds everything a plugin provides, overriding what is already registered with the same
+    /// names the same as adding its functions and templates one at a time would. context
+    /// defaults of a plugin added later override those of earlier ones. a plugin added twice
+    /// is installed again
+    pub fn plugin<P: SatoPlugin>(mut self, plugin: P) -> Self {
+        let name = plugin.name().to_string();
+        if self.plugins.contains(&name) {
+            self.errors.push(BuildError::DuplicatePlugin(name));
+        }
+        else {
+            self.plugins.push(name);
+        }
+        for (function, func) in plugin.functions() {
+            self = self.function(function, func);
+        }
+        for (template, expr) in plugin.templates() {
+            self = self.template(template, expr);
+        }
+        self.defaults.0.extend(plugin.context().0);
+        self
+    }
+
+    /// adds a named template for `include` and `extends`. like with functions, a name that is
+    /// already registered is overridden by the template added last, which `try_build` reports
+    /// as an error
+    pub fn template<S>(mut self, name: S, template: Template) -> Self
+    where
+        S: std::convert::Into<String>
+    {
+        let name = name.into();
+        if self.templates.contains_key(&name) {
+            self.errors.push(BuildError::TemplateAlreadyRegistered(name.clone()));
+        }
+        self.templates.insert(name, template);
+        self
+    }
+
+    pub fn loader(mut self, loader: Box<dyn TemplateLoader>) -> Self {
+        self.loader = Some(loader);
         self
     }
 
     pub fn build(self) -> Renderer {
         Renderer {
+            id: NEXT_RENDERER_ID.fetch_add(1, Ordering::Relaxed),
             functions: self.functions,
+            streaming: self.streaming,
+            templates: self.templates,
+            loader: self.loader,
+            dialect: self.dialect,
+            strict: self.strict,
+            limits: self.limits,
+            sandbox: self.sandbox,
+            defaults: self.defaults,
+        }
+    }
+
+    /// builds the renderer unless a function was added with a name that was already
+    /// registered or a plugin was added twice, which `build` lets override what came before
+    pub fn try_build(mut self) -> Result<Renderer, BuildError> {
+        if !self.errors.is_empty() {
+            return Err(self.errors.swap_remove(0))
         }
+        Ok(self.build())
     }
 }
 
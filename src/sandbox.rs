@@ -0,0 +1,121 @@
+use std::collections::HashSet;
+
+use crate::renderer::{Attribute, RenderError};
+
+
+// attributes whose values browsers follow as links or load from
+const URL_ATTRIBUTES: &[&str] = &[
+    "href", "src", "action", "formaction", "xlink:href", "cite", "poster", "background",
+];
+
+
+/// the builtins, tags and attributes a sandboxed renderer lets templates use, everything
+/// else is an error when the template is rendered. functions added with
+/// `RendererBuilder::function` need to be allowed too.
+///
+/// whatever is allowed, event handler attributes like `onclick` and `javascript:`,
+/// `vbscript:` and non-image `data:` urls are always rejected.
+#[derive(Debug, Clone, Default)]
+pub struct Sandbox {
+    functions: HashSet<String>,
+    tags: HashSet<String>,
+    attributes: HashSet<String>,
+}
+
+impl Sandbox {
+    /// a sandbox that allows nothing
+    pub fn new() -> Sandbox {
+        Sandbox::default()
+    }
+
+    /// builtins and text formatting markup for content written by users: no `raw`, no loading
+    /// other templates, no scripts, styles, frames or forms
+    pub fn user_content() -> Sandbox {
+        Sandbox::new()
+            .allow_functions([
+                "if", "when", "unless", "cond", "and", "or", "not", "switch", "case", "for", "get",
+                "is-set", "default", "null?", "is-null", "let", "with", "defcomponent", "children",
+                "eq", "ne", "lt", "gt", "lte", "gte", "+", "-", "*", "/", "%", "format-number",
+            ])
+            .allow_tags([
+                "p", "br", "hr", "h1", "h2", "h3", "h4", "h5", "h6", "div", "span", "a", "em",
+                "strong", "b", "i", "u", "s", "small", "sub", "sup", "mark", "blockquote", "q",
+                "cite", "code", "pre", "kbd", "ul", "ol", "li", "dl", "dt", "dd", "table", "thead",
+                "tbody", "tfoot", "tr", "th", "td", "caption", "img", "figure", "figcaption",
+                "section", "article", "aside", "header", "footer", "nav", "abbr", "time", "del",
+                "ins", "details", "summary",
+            ])
+            .allow_attributes([
+                "class", "id", "title", "lang", "dir", "href", "src", "alt", "width", "height",
+                "colspan", "rowspan", "datetime", "cite", "open", "start", "reversed",
+            ])
+    }
+
+    pub fn allow_functions<I, S>(mut self, names: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.functions.extend(names.into_iter().map(Into::into));
+        self
+    }
+
+    /// tag names are matched without regard to case
+    pub fn allow_tags<I, S>(mut self, tags: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.tags.extend(tags.into_iter().map(|t| t.into().to_ascii_lowercase()));
+        self
+    }
+
+    /// attribute names are matched without regard to case
+    pub fn allow_attributes<I, S>(mut self, attributes: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.attributes.extend(attributes.into_iter().map(|a| a.into().to_ascii_lowercase()));
+        self
+    }
+
+    pub(crate) fn check_function(&self, name: &str) -> Result<(), RenderError> {
+        if !self.functions.contains(name) {
+            return Err(RenderError::FunctionNotAllowed(name.into()))
+        }
+        Ok(())
+    }
+
+    pub(crate) fn check_tag(&self, tag: &str) -> Result<(), RenderError> {
+        if !self.tags.contains(&tag.to_ascii_lowercase()) {
+            return Err(RenderError::TagNotAllowed(tag.into()))
+        }
+        Ok(())
+    }
+
+    pub(crate) fn check_attribute(&self, tag: &str, attr: &Attribute) -> Result<(), RenderError> {
+        let name = attr.0.to_ascii_lowercase();
+        if name.starts_with("on") || !self.attributes.contains(&name) {
+            return Err(RenderError::AttributeNotAllowed(tag.into(), attr.0.clone()))
+        }
+        match &attr.1 {
+            Some(url) if URL_ATTRIBUTES.contains(&name.as_str()) && is_script_url(url) => {
+                Err(RenderError::UrlNotAllowed(tag.into(), attr.0.clone(), url.clone()))
+            },
+            _ => Ok(()),
+        }
+    }
+}
+
+// browsers skip whitespace and control characters anywhere in a url's scheme, so
+// `java\tscript:` is still javascript
+fn is_script_url(url: &str) -> bool {
+    let url = url.chars()
+        .filter(|c| !c.is_ascii_whitespace() && !c.is_control())
+        .collect::<String>()
+        .to_ascii_lowercase();
+    url.starts_with("javascript:")
+        || url.starts_with("vbscript:")
+        || (url.starts_with("data:") && !url.starts_with("data:image/"))
+}
@@ -0,0 +1,392 @@
+use std::collections::BTreeMap;
+use std::fmt::Display;
+
+use serde::ser::{self, Serialize, SerializeMap as _, SerializeSeq as _};
+
+use crate::context::{ContextValue, RenderContext};
+
+
+#[derive(thiserror::Error, Debug)]
+pub enum SerializeError {
+    #[error("{0}")]
+    Custom(String),
+    #[error("value is not a struct or map and cannot be used as a render context")]
+    NotAnObject,
+    #[error("map key is not a string or integer")]
+    KeyMustBeString,
+    #[error("integer {0} does not fit in an i64")]
+    IntegerOutOfRange(String),
+}
+
+impl ser::Error for SerializeError {
+    fn custom<T: Display>(msg: T) -> Self {
+        SerializeError::Custom(msg.to_string())
+    }
+}
+
+
+/// converts any serializable value into a `ContextValue`. struct fields and map entries that
+/// are `None` or `()` are left unset, anywhere else they become `Null`.
+pub fn to_context_value<T: Serialize + ?Sized>(value: &T) -> Result<ContextValue, SerializeError> {
+    Ok(value.serialize(Serializer)?
+       .unwrap_or(ContextValue::Null))
+}
+
+impl RenderContext {
+    /// builds a context from a serializable struct or map, its fields become the variables
+    pub fn from_serialize<T: Serialize + ?Sized>(value: &T) -> Result<RenderContext, SerializeError> {
+        match value.serialize(Serializer)? {
+            Some(ContextValue::Object(context)) => Ok(context),
+            _ => Err(SerializeError::NotAnObject),
+        }
+    }
+}
+
+
+// the other direction, so a context can be written back out with any serde format
+impl Serialize for ContextValue {
+    fn serialize<S: ser::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            ContextValue::Integer(i) => serializer.serialize_i64(*i),
+            ContextValue::Float(f) => serializer.serialize_f64(*f),
+            ContextValue::Boolean(b) => serializer.serialize_bool(*b),
+            ContextValue::String(s) | ContextValue::Safe(s) => serializer.serialize_str(s),
+            ContextValue::Vec(v) => {
+                let mut seq = serializer.serialize_seq(Some(v.len()))?;
+                for value in v {
+                    seq.serialize_element(value)?;
+                }
+                seq.end()
+            },
+            ContextValue::Object(o) => o.serialize(serializer),
+            ContextValue::Template(_) => serializer.serialize_str("<template>"),
+            ContextValue::Null => serializer.serialize_none(),
+        }
+    }
+}
+
+// null values leave their key out, the same as they are read back in, since formats like
+// toml have no null
+impl Serialize for RenderContext {
+    fn serialize<S: ser::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let entries = self.0.iter()
+            .filter(|(_, value)| !matches!(value, ContextValue::Null));
+        let mut map = serializer.serialize_map(Some(entries.clone().count()))?;
+        for (key, value) in entries {
+            map.serialize_entry(key, value)?;
+        }
+        map.end()
+    }
+}
+
+
+// `None` is used for values that should leave a key unset
+struct Serializer;
+
+fn integer<T: TryInto<i64> + Display + Copy>(v: T) -> Result<Option<ContextValue>, SerializeError> {
+    v.try_into()
+        .map(|i| Some(ContextValue::Integer(i)))
+        .map_err(|_| SerializeError::IntegerOutOfRange(v.to_string()))
+}
+
+fn object(map: BTreeMap<String, ContextValue>) -> Option<ContextValue> {
+    Some(ContextValue::Object(RenderContext(map)))
+}
+
+// enum variants with data become an object with the variant name as its only key
+fn variant(name: &str, value: ContextValue) -> Option<ContextValue> {
+    object(BTreeMap::from([(name.to_string(), value)]))
+}
+
+impl ser::Serializer for Serializer {
+    type Ok = Option<ContextValue>;
+    type Error = SerializeError;
+
+    type SerializeSeq = SerializeVec;
+    type SerializeTuple = SerializeVec;
+    type SerializeTupleStruct = SerializeVec;
+    type SerializeTupleVariant = SerializeVec;
+    type SerializeMap = SerializeMap;
+    type SerializeStruct = SerializeMap;
+    type SerializeStructVariant = SerializeMap;
+
+    fn serialize_bool(self, v: bool) -> Result<Self::Ok, Self::Error> {
+        Ok(Some(ContextValue::Boolean(v)))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<Self::Ok, Self::Error> {
+        integer(v)
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<Self::Ok, Self::Error> {
+        integer(v)
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<Self::Ok, Self::Error> {
+        integer(v)
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<Self::Ok, Self::Error> {
+        integer(v)
+    }
+
+    fn serialize_i128(self, v: i128) -> Result<Self::Ok, Self::Error> {
+        integer(v)
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<Self::Ok, Self::Error> {
+        integer(v)
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<Self::Ok, Self::Error> {
+        integer(v)
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<Self::Ok, Self::Error> {
+        integer(v)
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<Self::Ok, Self::Error> {
+        integer(v)
+    }
+
+    fn serialize_u128(self, v: u128) -> Result<Self::Ok, Self::Error> {
+        integer(v)
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<Self::Ok, Self::Error> {
+        Ok(Some(ContextValue::Float(v as f64)))
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<Self::Ok, Self::Error> {
+        Ok(Some(ContextValue::Float(v)))
+    }
+
+    fn serialize_char(self, v: char) -> Result<Self::Ok, Self::Error> {
+        Ok(Some(ContextValue::String(v.to_string())))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<Self::Ok, Self::Error> {
+        Ok(Some(ContextValue::String(v.into())))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<Self::Ok, Self::Error> {
+        Ok(Some(ContextValue::Vec(v.iter().map(|b| ContextValue::Integer(*b as i64)).collect())))
+    }
+
+    fn serialize_none(self) -> Result<Self::Ok, Self::Error> {
+        Ok(None)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Self::Ok, Self::Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Self::Ok, Self::Error> {
+        Ok(None)
+    }
+
+    fn serialize_unit_struct(self, _: &'static str) -> Result<Self::Ok, Self::Error> {
+        Ok(None)
+    }
+
+    fn serialize_unit_variant(self, _: &'static str, _: u32, variant: &'static str) -> Result<Self::Ok, Self::Error> {
+        Ok(Some(ContextValue::String(variant.into())))
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(self, _: &'static str, value: &T) -> Result<Self::Ok, Self::Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(self, _: &'static str, _: u32, name: &'static str, value: &T) -> Result<Self::Ok, Self::Error> {
+        Ok(variant(name, to_context_value(value)?))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<Self::SerializeSeq, Self::Error> {
+        Ok(SerializeVec {
+            variant: None,
+            values: Vec::with_capacity(len.unwrap_or(0)),
+        })
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<Self::SerializeTuple, Self::Error> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(self, _: &'static str, len: usize) -> Result<Self::SerializeTupleStruct, Self::Error> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(self, _: &'static str, _: u32, name: &'static str, len: usize) -> Result<Self::SerializeTupleVariant, Self::Error> {
+        Ok(SerializeVec {
+            variant: Some(name),
+            values: Vec::with_capacity(len),
+        })
+    }
+
+    fn serialize_map(self, _: Option<usize>) -> Result<Self::SerializeMap, Self::Error> {
+        Ok(SerializeMap {
+            variant: None,
+            map: BTreeMap::new(),
+            key: None,
+        })
+    }
+
+    fn serialize_struct(self, _: &'static str, len: usize) -> Result<Self::SerializeStruct, Self::Error> {
+        self.serialize_map(Some(len))
+    }
+
+    fn serialize_struct_variant(self, _: &'static str, _: u32, name: &'static str, _: usize) -> Result<Self::SerializeStructVariant, Self::Error> {
+        Ok(SerializeMap {
+            variant: Some(name),
+            map: BTreeMap::new(),
+            key: None,
+        })
+    }
+}
+
+
+struct SerializeVec {
+    variant: Option<&'static str>,
+    values: Vec<ContextValue>,
+}
+
+impl SerializeVec {
+    fn push<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerializeError> {
+        self.values.push(to_context_value(value)?);
+        Ok(())
+    }
+
+    fn finish(self) -> Option<ContextValue> {
+        let values = ContextValue::Vec(self.values);
+        match self.variant {
+            Some(name) => variant(name, values),
+            None => Some(values),
+        }
+    }
+}
+
+impl ser::SerializeSeq for SerializeVec {
+    type Ok = Option<ContextValue>;
+    type Error = SerializeError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Self::Error> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Self::Ok, Self::Error> {
+        Ok(self.finish())
+    }
+}
+
+impl ser::SerializeTuple for SerializeVec {
+    type Ok = Option<ContextValue>;
+    type Error = SerializeError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Self::Error> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Self::Ok, Self::Error> {
+        Ok(self.finish())
+    }
+}
+
+impl ser::SerializeTupleStruct for SerializeVec {
+    type Ok = Option<ContextValue>;
+    type Error = SerializeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Self::Error> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Self::Ok, Self::Error> {
+        Ok(self.finish())
+    }
+}
+
+impl ser::SerializeTupleVariant for SerializeVec {
+    type Ok = Option<ContextValue>;
+    type Error = SerializeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Self::Error> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Self::Ok, Self::Error> {
+        Ok(self.finish())
+    }
+}
+
+
+struct SerializeMap {
+    variant: Option<&'static str>,
+    map: BTreeMap<String, ContextValue>,
+    key: Option<String>,
+}
+
+impl SerializeMap {
+    fn insert<T: Serialize + ?Sized>(&mut self, key: String, value: &T) -> Result<(), SerializeError> {
+        if let Some(value) = value.serialize(Serializer)? {
+            self.map.insert(key, value);
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Option<ContextValue> {
+        match self.variant {
+            Some(name) => variant(name, ContextValue::Object(RenderContext(self.map))),
+            None => object(self.map),
+        }
+    }
+}
+
+impl ser::SerializeMap for SerializeMap {
+    type Ok = Option<ContextValue>;
+    type Error = SerializeError;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), Self::Error> {
+        self.key = Some(match key.serialize(Serializer)? {
+            Some(ContextValue::String(s)) => s,
+            Some(ContextValue::Integer(i)) => i.to_string(),
+            _ => return Err(SerializeError::KeyMustBeString),
+        });
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Self::Error> {
+        let key = self.key.take()
+            .ok_or_else(|| SerializeError::Custom("map value serialized before its key".into()))?;
+        self.insert(key, value)
+    }
+
+    fn end(self) -> Result<Self::Ok, Self::Error> {
+        Ok(self.finish())
+    }
+}
+
+impl ser::SerializeStruct for SerializeMap {
+    type Ok = Option<ContextValue>;
+    type Error = SerializeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T) -> Result<(), Self::Error> {
+        self.insert(key.into(), value)
+    }
+
+    fn end(self) -> Result<Self::Ok, Self::Error> {
+        Ok(self.finish())
+    }
+}
+
+impl ser::SerializeStructVariant for SerializeMap {
+    type Ok = Option<ContextValue>;
+    type Error = SerializeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T) -> Result<(), Self::Error> {
+        self.insert(key.into(), value)
+    }
+
+    fn end(self) -> Result<Self::Ok, Self::Error> {
+        Ok(self.finish())
+    }
+}
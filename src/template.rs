@@ -1,17 +1,95 @@
+use std::fmt;
 use std::io::Read;
+use std::sync::Arc;
 
 #[derive(thiserror::Error, Debug)]
 pub enum ParseExprError {
-    #[error("expr is not an atom: {0:?}")]
-    NotAnAtom(sexp::Atom),
-    #[error("expr is not a list: {0:?}")]
-    NotAList(Vec<sexp::Sexp>),
-    #[error("@ attribute is not a list {0:?} {1:?}")]
-    NotAnAttribute(sexp::Sexp, Vec<sexp::Sexp>),
-    #[error("html attribute is missing an element {0:?}")]
-    AttributeMissingElement(Vec<sexp::Sexp>),
+    #[error("list does not start with a tag name\n{0}")]
+    NotAList(Span),
+    #[error("@ attribute is not a list\n{0}")]
+    NotAnAttribute(Span),
+    #[error("html attribute is missing an element\n{0}")]
+    AttributeMissingElement(Span),
 }
 
+
+#[derive(Debug)]
+struct Source {
+    name: Option<String>,
+    text: String,
+}
+
+/// the position of an expression in the template it was parsed from
+#[derive(Clone, Default)]
+pub struct Span {
+    source: Option<Arc<Source>>,
+    line: usize,
+    column: usize,
+}
+
+impl Span {
+    fn new(source: &Arc<Source>, line: usize, column: usize) -> Span {
+        Span {
+            source: Some(source.clone()),
+            line,
+            column,
+        }
+    }
+
+    /// the file name of the template, if it was given one
+    pub fn file(&self) -> Option<&str> {
+        self.source
+            .as_ref()
+            .and_then(|s| s.name.as_deref())
+    }
+
+    /// 1-based line number, 0 if the span is not from a parsed template
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    /// 1-based column number, 0 if the span is not from a parsed template
+    pub fn column(&self) -> usize {
+        self.column
+    }
+
+    pub fn is_known(&self) -> bool {
+        self.source.is_some()
+    }
+}
+
+impl fmt::Debug for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.file().unwrap_or("<template>"), self.line, self.column)
+    }
+}
+
+/// prints the location followed by the source line with a caret under the column
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let source = match &self.source {
+            Some(source) => source,
+            None => return write!(f, " --> <unknown location>"),
+        };
+        let text = source.text
+            .lines()
+            .nth(self.line.saturating_sub(1))
+            .unwrap_or_default();
+        // keep tabs so the caret lines up with the source line
+        let padding = text.chars()
+            .take(self.column.saturating_sub(1))
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect::<String>();
+        let gutter = " ".repeat(self.line.to_string().len());
+
+        writeln!(f, "{}--> {:?}", gutter, self)?;
+        writeln!(f, "{} |", gutter)?;
+        writeln!(f, "{} | {}", self.line, text)?;
+        write!(f, "{} | {}^", gutter, padding)
+    }
+}
+
+
 #[derive(Debug, Clone)]
 pub struct TemplateAttribute(pub TemplateExprNode, pub Vec<TemplateExprNode>);
 
@@ -20,12 +98,17 @@ pub struct TemplateTag {
     pub tag: String,
     pub attrs: Vec<TemplateAttribute>,
     pub children: Vec<TemplateExprNode>,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone)]
 pub enum TemplateExprNode {
-    Identifier(String),
-    Integer(i64),
+    /// a bare symbol, or a variable when it starts with `$`
+    Identifier(String, Span),
+    /// quoted text with its escapes already applied, never expanded as a variable
+    String(String, Span),
+    Integer(i64, Span),
+    Float(f64, Span),
     Tag(TemplateTag)
 }
 
@@ -33,30 +116,40 @@ impl TryFrom<String> for TemplateExprNode {
     type Error = TemplateError;
     
     fn try_from(other: String) -> Result<TemplateExprNode, Self::Error> {
-        Ok(parse_expr(&sexp::parse(&other).map_err(|err| TemplateError::ParseError(err, other.into()))?)?)
+        parse(None, &other)
     }
 }
 
 impl TemplateExprNode {
     pub fn as_identifier(&self) -> Option<&String> {
         match self {
-            TemplateExprNode::Identifier(s) => Some(s),
+            TemplateExprNode::Identifier(s, _) => Some(s),
             _ => None
         }
     }
 
     pub fn as_integer(&self) -> Option<i64> {
         match self {
-            TemplateExprNode::Integer(i) => Some(*i),
+            TemplateExprNode::Integer(i, _) => Some(*i),
             _ => None
         }
     }
+
+    pub fn span(&self) -> &Span {
+        match self {
+            TemplateExprNode::Identifier(_, span) => span,
+            TemplateExprNode::String(_, span) => span,
+            TemplateExprNode::Integer(_, span) => span,
+            TemplateExprNode::Float(_, span) => span,
+            TemplateExprNode::Tag(tag) => &tag.span,
+        }
+    }
 }
 
 pub fn get_children_by_tag(expr: &[TemplateExprNode], tag_name: &str) -> Vec<TemplateTag> {
     expr
         .iter()
-        .map(|e| {
+        .flat_map(|e| {
             if let TemplateExprNode::Tag(tag) = e {
                 if tag.tag == tag_name {
                     std::iter::once(tag)
@@ -72,65 +165,292 @@ pub fn get_children_by_tag(expr: &[TemplateExprNode], tag_name: &str) -> Vec<Tem
                 Vec::new()
             }
         })
-        .flatten()
         .collect()
 }
 
-fn parse_attrs(attrs: &Vec<sexp::Sexp>) -> Result<Vec<TemplateAttribute>, ParseExprError> {
-    attrs.iter().skip(1)
-        .map(|attr| {
-            match attr {
-                sexp::Sexp::List(list) => {
-                    let name = parse_expr(list
-                        .get(0)
-                        .ok_or_else(|| ParseExprError::AttributeMissingElement(attrs.clone()))?)?;
-                    let value = list
-                        .get(1..)
-                        .ok_or_else(|| ParseExprError::AttributeMissingElement(attrs.clone()))?
-                        .iter()
-                        .map(|v| {
-                            parse_expr(v)
-                        })
-                        .collect::<Result<Vec<_>, _>>()?;
-                    Ok(TemplateAttribute(name, value))
+struct Parser<'a> {
+    source: &'a Arc<Source>,
+    text: &'a str,
+    pos: usize,
+    line: usize,
+    column: usize,
+    depth: usize,
+}
+
+// lists nested deeper than this are an error rather than a risk of overflowing the stack
+const MAX_NESTING: usize = 256;
+
+impl Parser<'_> {
+    fn rest(&self) -> &str {
+        &self.text[self.pos..]
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        }
+        else {
+            self.column += 1;
+        }
+        Some(c)
+    }
+
+    fn span(&self) -> Span {
+        Span::new(self.source, self.line, self.column)
+    }
+
+    fn error<S: Into<String>>(&self, message: S, span: Span) -> TemplateError {
+        TemplateError::ParseError(message.into(), span)
+    }
+
+    // skips whitespace, `; line comments` and `#| block comments |#`, which can be nested
+    fn skip_space(&mut self) -> Result<(), TemplateError> {
+        while let Some(c) = self.peek() {
+            if c == ';' {
+                while !matches!(self.bump(), Some('\n') | None) {}
+            }
+            else if self.rest().starts_with("#|") {
+                let start = self.span();
+                let mut depth = 0;
+                loop {
+                    if self.rest().starts_with("#|") {
+                        depth += 1;
+                        self.pos += 2;
+                        self.column += 2;
+                    }
+                    else if self.rest().starts_with("|#") {
+                        depth -= 1;
+                        self.pos += 2;
+                        self.column += 2;
+                        if depth == 0 {
+                            break
+                        }
+                    }
+                    else if self.bump().is_none() {
+                        return Err(self.error("unterminated block comment", start))
+                    }
                 }
-                _ => Err(ParseExprError::NotAnAttribute(attr.clone(), attrs.clone()))
             }
-        })
-        .collect::<Result<Vec<_>, ParseExprError>>()
-}
+            else if c.is_whitespace() {
+                self.bump();
+            }
+            else {
+                break
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_expr(&mut self) -> Result<TemplateExprNode, TemplateError> {
+        self.skip_space()?;
+        match self.peek() {
+            Some('(') => self.parse_list(),
+            Some(')') => Err(self.error("unexpected `)`", self.span())),
+            Some('"') => self.parse_string(),
+            Some(_) => Ok(self.parse_atom()),
+            None => Err(self.error("unexpected end of template", self.span())),
+        }
+    }
+
+    // parses expressions up to and including the `)` closing the list opened at `open`
+    fn parse_until_close(&mut self, open: &Span) -> Result<Vec<TemplateExprNode>, TemplateError> {
+        let mut exprs = Vec::new();
+        loop {
+            self.skip_space()?;
+            match self.peek() {
+                Some(')') => {
+                    self.bump();
+                    return Ok(exprs)
+                },
+                Some(_) => exprs.push(self.parse_expr()?),
+                None => return Err(self.error("unclosed `(`", open.clone())),
+            }
+        }
+    }
+
+    fn at_attributes(&self) -> bool {
+        match self.rest().strip_prefix("(@") {
+            Some(rest) => rest.chars().next().is_none_or(|c| c.is_whitespace() || matches!(c, '(' | ')' | ';')),
+            None => false,
+        }
+    }
+
+    fn parse_list(&mut self) -> Result<TemplateExprNode, TemplateError> {
+        let span = self.span();
+        if self.depth == MAX_NESTING {
+            return Err(self.error(format!("lists are nested more than {} deep", MAX_NESTING), span))
+        }
+        self.depth += 1;
+        let list = self.parse_list_contents(span);
+        self.depth -= 1;
+        list
+    }
+
+    fn parse_list_contents(&mut self, span: Span) -> Result<TemplateExprNode, TemplateError> {
+        self.bump();
+        self.skip_space()?;
+
+        let tag = match self.peek() {
+            // `()` and lists of lists like `((a 1) (b 2))` are tags without a name, which
+            // builtins like `let` read. `()` evaluates to nothing and the others to an error
+            Some('(') | Some(')') => String::new(),
+            None => return Err(self.error("unclosed `(`", span)),
+            Some(_) => match self.parse_expr()? {
+                TemplateExprNode::Identifier(tag, _) => tag,
+                _ => return Err(ParseExprError::NotAList(span).into()),
+            },
+        };
+
+        self.skip_space()?;
+        let attrs = if !tag.is_empty() && self.at_attributes() {
+            self.parse_attrs()?
+        }
+        else {
+            Vec::new()
+        };
+
+        let children = self.parse_until_close(&span)?;
+        Ok(TemplateExprNode::Tag(TemplateTag {
+            tag,
+            attrs,
+            children,
+            span,
+        }))
+    }
+
+    fn parse_attrs(&mut self) -> Result<Vec<TemplateAttribute>, TemplateError> {
+        let span = self.span();
+        self.bump();
+        self.bump();
+
+        let mut attrs = Vec::new();
+        loop {
+            self.skip_space()?;
+            let attr_span = self.span();
+            match self.peek() {
+                Some(')') => {
+                    self.bump();
+                    return Ok(attrs)
+                },
+                Some('(') => {
+                    self.bump();
+                    self.skip_space()?;
+                    if self.peek() == Some(')') {
+                        return Err(ParseExprError::AttributeMissingElement(attr_span).into())
+                    }
+                    let name = self.parse_expr()?;
+                    let value = self.parse_until_close(&attr_span)?;
+                    attrs.push(TemplateAttribute(name, value));
+                },
+                Some(_) => return Err(ParseExprError::NotAnAttribute(attr_span).into()),
+                None => return Err(self.error("unclosed `(`", span)),
+            }
+        }
+    }
+
+    fn parse_string(&mut self) -> Result<TemplateExprNode, TemplateError> {
+        let span = self.span();
+        self.bump();
 
-fn parse_expr(expr: &sexp::Sexp) -> Result<TemplateExprNode, ParseExprError> {
-    Ok(match expr {
-        sexp::Sexp::Atom(atom) => {
-            match atom {
-                sexp::Atom::S(s) => TemplateExprNode::Identifier(s.to_string()),
-                sexp::Atom::I(i) => TemplateExprNode::Integer(*i),
-                //sexp::Atom::I(i) => TemplateExprNode::Integer(i),
-                _ => return Err(ParseExprError::NotAnAtom(atom.clone()))
+        let mut string = String::new();
+        loop {
+            let escape_span = self.span();
+            match self.bump() {
+                Some('"') => break,
+                Some('\\') => {
+                    let c = match self.bump() {
+                        Some('"') => '"',
+                        Some('\\') => '\\',
+                        Some('n') => '\n',
+                        Some('r') => '\r',
+                        Some('t') => '\t',
+                        Some('0') => '\0',
+                        Some('u') => self.parse_unicode_escape(&escape_span)?,
+                        Some(c) => return Err(self.error(format!("unknown escape `\\{}`", c), escape_span)),
+                        None => return Err(self.error("unterminated string", span)),
+                    };
+                    string.push(c);
+                },
+                Some(c) => string.push(c),
+                None => return Err(self.error("unterminated string", span)),
             }
-        },
-        sexp::Sexp::List(list) => {
-            let tag = match list[0] {
-                sexp::Sexp::Atom(sexp::Atom::S(ref s)) => s.clone(),
-                _ => return Err(ParseExprError::NotAList(list.clone()))
-            };
-            let (attrs, attr_index) = match &list.get(1) {
-                Some(sexp::Sexp::List(list)) if list.get(0) == Some(&sexp::Sexp::Atom(sexp::Atom::S("@".into()))) => (parse_attrs(&list)?, 2),
-                _ => (Vec::new(), 1)
-            };
-
-            let children = list.iter().skip(attr_index).map(|l| {
-                parse_expr(l)
-            }).collect::<Result<Vec<_>, ParseExprError>>()?;
-
-            TemplateExprNode::Tag(TemplateTag {
-                tag,
-                attrs,
-                children,
-            })
-        }
-    })
+        }
+        Ok(TemplateExprNode::String(string, span))
+    }
+
+    // `\u{1F600}`, the `\u` has already been read
+    fn parse_unicode_escape(&mut self, span: &Span) -> Result<char, TemplateError> {
+        let invalid = || TemplateError::ParseError("invalid unicode escape".into(), span.clone());
+        if self.bump() != Some('{') {
+            return Err(invalid())
+        }
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_hexdigit()) {
+            self.bump();
+        }
+        let digits = &self.text[start..self.pos];
+        if self.bump() != Some('}') || digits.is_empty() || digits.len() > 6 {
+            return Err(invalid())
+        }
+        u32::from_str_radix(digits, 16)
+            .ok()
+            .and_then(char::from_u32)
+            .ok_or_else(invalid)
+    }
+
+    fn parse_atom(&mut self) -> TemplateExprNode {
+        let span = self.span();
+        let start = self.pos;
+        while let Some(c) = self.peek() {
+            if c.is_whitespace() || matches!(c, '(' | ')' | ';') {
+                break
+            }
+            self.bump();
+        }
+        let atom = &self.text[start..self.pos];
+
+        // only things that look like numbers are numbers, `inf` and `nan` are symbols
+        let numeric = atom.chars().any(|c| c.is_ascii_digit())
+            && atom.chars().all(|c| c.is_ascii_digit() || matches!(c, '+' | '-' | '.' | 'e' | 'E'));
+        if numeric {
+            if let Ok(i) = atom.parse::<i64>() {
+                return TemplateExprNode::Integer(i, span)
+            }
+            if let Ok(f) = atom.parse::<f64>() {
+                return TemplateExprNode::Float(f, span)
+            }
+        }
+        TemplateExprNode::Identifier(atom.into(), span)
+    }
+}
+
+fn parse(name: Option<&str>, template: &str) -> Result<TemplateExprNode, TemplateError> {
+    let source = Arc::new(Source {
+        name: name.map(Into::into),
+        text: template.into(),
+    });
+    let mut parser = Parser {
+        source: &source,
+        text: template,
+        pos: 0,
+        line: 1,
+        column: 1,
+        depth: 0,
+    };
+
+    let expr = parser.parse_expr()?;
+    parser.skip_space()?;
+    if parser.peek().is_some() {
+        return Err(parser.error("unexpected input after the template", parser.span()))
+    }
+    Ok(expr)
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -139,9 +459,13 @@ pub enum TemplateError {
     NoFile,
     #[error("invalid file")]
     InvalidFile,
-    #[error("error parsing template")]
-    ParseError(Box<sexp::Error>, String),
-    #[error("error parsing template expression")]
+    #[error("template `{0}` not found")]
+    NotFound(String),
+    #[error("template path `{0}` is outside of the template directory")]
+    InvalidPath(String),
+    #[error("error parsing template: {0}\n{1}")]
+    ParseError(String, Span),
+    #[error("error parsing template expression: {0}")]
     ParseExprError(#[from] ParseExprError),
 }
 
@@ -151,16 +475,24 @@ pub struct Template {
 }
 
 impl Template {
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(template: &str) -> Result<Template, TemplateError> {
         Ok(Template {
-            expr: parse_expr(&sexp::parse(template).map_err(|err| TemplateError::ParseError(err, template.into()))?)?
+            expr: parse(None, template)?
+        })
+    }
+
+    /// like `from_str`, with a name that error messages use to refer to the template
+    pub fn from_named_str(name: &str, template: &str) -> Result<Template, TemplateError> {
+        Ok(Template {
+            expr: parse(Some(name), template)?
         })
     }
 
     pub fn from_path<P: AsRef<std::path::Path>>(template: P) -> Result<Template, TemplateError> {
-        let mut f = std::fs::File::open(template).map_err(|_| TemplateError::NoFile)?;
+        let mut f = std::fs::File::open(&template).map_err(|_| TemplateError::NoFile)?;
         let mut s = String::new();
         f.read_to_string(&mut s).map_err(|_| TemplateError::InvalidFile)?;
-        Template::from_str(&s)
+        Template::from_named_str(&template.as_ref().display().to_string(), &s)
     }
 }